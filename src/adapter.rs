@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::history::{ApiResult, Time, STATUS_NO_DATA, STATUS_OK};
+
+/// Turns the body of a `history` response into the UDF layout the rest of the tool works with.
+/// Its `into_structured` then gives the candles as a `StructuredApiResult`, so gap detection
+/// doesn't depend on how a provider shapes its responses.
+pub trait ResponseAdapter: fmt::Debug + Send + Sync {
+    fn parse(&self, body: &str) -> anyhow::Result<ApiResult>;
+}
+
+/// How closely UDF responses are checked against the fields `ApiResult` knows, to notice when
+/// the provider changes its API.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaCheck {
+    /// Extra fields are ignored, like serde does
+    Off,
+    /// Extra fields are logged, once per field. Known fields of the wrong type are errors
+    /// naming the field.
+    #[default]
+    Warn,
+    /// Like `Warn`, but extra fields are errors too, and so are time and price columns missing
+    /// from an `ok` response
+    Strict,
+}
+
+impl FromStr for SchemaCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(SchemaCheck::Off),
+            "warn" => Ok(SchemaCheck::Warn),
+            "strict" => Ok(SchemaCheck::Strict),
+            _ => anyhow::bail!(
+                "Unknown schema check {:?}. Expected one of: off, warn, strict",
+                s
+            ),
+        }
+    }
+}
+
+/// UDF's `{s, time[], open[], ...}` columns, as is.
+#[derive(Debug, Default)]
+pub struct UdfAdapter {
+    schema_check: SchemaCheck,
+    /// Extra fields that were already logged
+    reported: Mutex<HashSet<String>>,
+}
+
+impl UdfAdapter {
+    pub fn new(schema_check: SchemaCheck) -> Self {
+        UdfAdapter {
+            schema_check,
+            reported: Mutex::default(),
+        }
+    }
+}
+
+/// Columns an `ok` response can't do without. `volume` is optional.
+const CANDLE_COLUMNS: [&str; 5] = ["time", "open", "high", "low", "close"];
+
+/// What a known UDF field must hold, `None` for fields `ApiResult` doesn't know.
+fn udf_field_kind(name: &str) -> Option<FieldKind> {
+    match name {
+        "s" | "errmsg" => Some(FieldKind::String),
+        "nextTime" => Some(FieldKind::Integer),
+        "time" => Some(FieldKind::Integers),
+        "volume" => Some(FieldKind::Numbers),
+        "open" | "high" | "low" | "close" => Some(FieldKind::Prices),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FieldKind {
+    String,
+    Integer,
+    Integers,
+    Numbers,
+    /// Numbers or `null`
+    Prices,
+}
+
+impl FieldKind {
+    fn description(self) -> &'static str {
+        match self {
+            FieldKind::String => "a string",
+            FieldKind::Integer => "an integer",
+            FieldKind::Integers => "an array of integers",
+            FieldKind::Numbers => "an array of numbers",
+            FieldKind::Prices => "an array of numbers or nulls",
+        }
+    }
+
+    /// What's wrong with `value`, if anything.
+    fn check(self, value: &Value) -> Option<String> {
+        let element_ok: fn(&Value) -> bool = match self {
+            FieldKind::String => return (!value.is_string()).then(|| json_kind(value).to_string()),
+            FieldKind::Integer => return (!value.is_i64()).then(|| json_kind(value).to_string()),
+            FieldKind::Integers => Value::is_i64,
+            FieldKind::Numbers => Value::is_number,
+            FieldKind::Prices => |value| value.is_number() || value.is_null(),
+        };
+        let Value::Array(elements) = value else {
+            return Some(json_kind(value).to_string());
+        };
+        let (index, element) = elements
+            .iter()
+            .enumerate()
+            .find(|(_, element)| !element_ok(element))?;
+        Some(format!("{} at index {}", json_kind(element), index))
+    }
+}
+
+fn json_kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(number) if number.is_i64() || number.is_u64() => "an integer",
+        Value::Number(_) => "a fractional number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+impl ResponseAdapter for UdfAdapter {
+    fn parse(&self, body: &str) -> anyhow::Result<ApiResult> {
+        if self.schema_check == SchemaCheck::Off {
+            return Ok(serde_json::from_str(body)?);
+        }
+
+        let value: Value = serde_json::from_str(body)?;
+        let Value::Object(fields) = &value else {
+            anyhow::bail!("Expected a JSON object, found {}", json_kind(&value));
+        };
+        for (name, field) in fields {
+            match udf_field_kind(name) {
+                Some(kind) => {
+                    if let Some(found) = kind.check(field) {
+                        anyhow::bail!(
+                            "`{}` should be {}, found {}",
+                            name,
+                            kind.description(),
+                            found
+                        );
+                    }
+                }
+                None if self.schema_check == SchemaCheck::Strict => {
+                    anyhow::bail!("Unexpected field `{}`, the API may have changed", name)
+                }
+                None => {
+                    if self
+                        .reported
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(name.clone())
+                    {
+                        tracing::warn!(
+                            "Unexpected field `{}` in a history response, the API may have changed",
+                            name
+                        );
+                    }
+                }
+            }
+        }
+        if self.schema_check == SchemaCheck::Strict
+            && fields.get("s").and_then(Value::as_str) == Some(STATUS_OK)
+        {
+            if let Some(column) = CANDLE_COLUMNS
+                .iter()
+                .find(|column| !fields.contains_key(**column))
+            {
+                anyhow::bail!("`ok` response without `{}`", column);
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// An array with one `{time, open, high, low, close, volume}` object per candle. An empty
+/// array counts as `no_data` and `null` prices as NaN, like in UDF responses. A `volume`
+/// missing from some rows counts as zero, missing from every row as no volume at all.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RowWiseAdapter;
+
+#[derive(Deserialize)]
+struct Row {
+    time: Time,
+    open: Option<f64>,
+    high: Option<f64>,
+    low: Option<f64>,
+    close: Option<f64>,
+    volume: Option<f64>,
+}
+
+impl ResponseAdapter for RowWiseAdapter {
+    fn parse(&self, body: &str) -> anyhow::Result<ApiResult> {
+        let rows: Vec<Row> = serde_json::from_str(body).context("Expected an array of candles")?;
+        let status = if rows.is_empty() {
+            STATUS_NO_DATA
+        } else {
+            STATUS_OK
+        };
+        let mut result = ApiResult {
+            s: status.to_string(),
+            time: Vec::with_capacity(rows.len()),
+            close: Vec::with_capacity(rows.len()),
+            open: Vec::with_capacity(rows.len()),
+            high: Vec::with_capacity(rows.len()),
+            low: Vec::with_capacity(rows.len()),
+            volume: None,
+            next_time: None,
+            errmsg: None,
+        };
+        let has_volume = rows.iter().any(|row| row.volume.is_some());
+        if has_volume {
+            result.volume = Some(Vec::with_capacity(rows.len()));
+        }
+        for row in rows {
+            result.time.push(row.time);
+            result.open.push(row.open.unwrap_or(f64::NAN));
+            result.high.push(row.high.unwrap_or(f64::NAN));
+            result.low.push(row.low.unwrap_or(f64::NAN));
+            result.close.push(row.close.unwrap_or(f64::NAN));
+            if let Some(volume) = &mut result.volume {
+                volume.push(row.volume.unwrap_or(0.0));
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Built-in adapters, by the name they're selected with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponseFormat {
+    #[default]
+    Udf,
+    RowWise,
+}
+
+impl ResponseFormat {
+    /// `schema_check` only applies to UDF responses.
+    pub fn adapter(self, schema_check: SchemaCheck) -> Arc<dyn ResponseAdapter> {
+        match self {
+            ResponseFormat::Udf => Arc::new(UdfAdapter::new(schema_check)),
+            ResponseFormat::RowWise => Arc::new(RowWiseAdapter),
+        }
+    }
+}
+
+impl FromStr for ResponseFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "udf" => Ok(ResponseFormat::Udf),
+            "row-wise" => Ok(ResponseFormat::RowWise),
+            _ => anyhow::bail!(
+                "Unknown response format {:?}. Expected one of: udf, row-wise",
+                s
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_wise_volume_is_unavailable_only_when_no_row_has_one() {
+        let parse = |body: &str| RowWiseAdapter.parse(body).unwrap();
+        let without = parse(
+            r#"[{"time":60,"open":1,"high":2,"low":0,"close":1},
+                {"time":120,"open":1,"high":2,"low":0,"close":1}]"#,
+        );
+        assert_eq!(without.time, vec![60, 120]);
+        assert!(!without.has_volume());
+
+        let some = parse(
+            r#"[{"time":60,"open":1,"high":2,"low":0,"close":1,"volume":5.5},
+                {"time":120,"open":1,"high":2,"low":0,"close":1}]"#,
+        );
+        assert_eq!(some.volume, Some(vec![5.5, 0.0]));
+    }
+}
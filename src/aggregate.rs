@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use crate::history::{CandleData, StructuredApiResult, Time};
+
+/// Combines consecutive candles into one: the first open, the last close, the extreme high and
+/// low and the total volume. `None` if there are no candles.
+pub fn aggregate_candles<'a>(
+    candles: impl IntoIterator<Item = &'a CandleData>,
+) -> Option<CandleData> {
+    candles.into_iter().fold(None, |aggregate, candle| {
+        Some(match aggregate {
+            None => candle.clone(),
+            Some(aggregate) => CandleData {
+                open: aggregate.open,
+                high: aggregate.high.max(candle.high),
+                low: aggregate.low.min(candle.low),
+                close: candle.close,
+                volume: aggregate.volume + candle.volume,
+            },
+        })
+    })
+}
+
+impl StructuredApiResult {
+    /// Builds higher-resolution candles out of these ones. Each bucket is the start time of a
+    /// higher-resolution candle with the lower-resolution candle times it's made of, in order.
+    ///
+    /// Buckets with any of their candles missing are left out, since they can't be compared
+    /// meaningfully. Their start times are returned as the second element.
+    pub fn aggregate(&self, buckets: &[(Time, Vec<Time>)]) -> (StructuredApiResult, Vec<Time>) {
+        let mut aggregated = HashMap::with_capacity(buckets.len());
+        let mut incomplete = vec![];
+        for (start, times) in buckets {
+            let candles: Option<Vec<&CandleData>> =
+                times.iter().map(|time| self.0.get(time)).collect();
+            match candles.and_then(aggregate_candles) {
+                Some(candle) => {
+                    aggregated.insert(*start, candle);
+                }
+                None => incomplete.push(*start),
+            }
+        }
+        (StructuredApiResult(aggregated), incomplete)
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::json;
+use url::Url;
+
+use crate::report::{group_gaps, GapRange, GapReport};
+use crate::resolution::Resolution;
+
+/// Body of a webhook request. `json` posts a `GapAlert` as is, `slack` wraps its text in a
+/// Slack incoming-webhook message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WebhookFormat {
+    #[default]
+    Json,
+    Slack,
+}
+
+impl FromStr for WebhookFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(WebhookFormat::Json),
+            "slack" => Ok(WebhookFormat::Slack),
+            _ => anyhow::bail!(
+                "Unknown webhook format {:?}. Expected one of: json, slack",
+                s
+            ),
+        }
+    }
+}
+
+/// Missing candles of one symbol and resolution within a single check.
+#[derive(Debug, Clone, Serialize)]
+pub struct GapAlert {
+    pub symbol: String,
+    pub resolution: Resolution,
+    pub expected: usize,
+    pub missing: usize,
+    pub availability: f64,
+    pub gaps: Vec<GapRange>,
+}
+
+impl GapAlert {
+    /// One alert per symbol and resolution whose missing candles across `reports` exceed
+    /// `threshold`. Failed periods don't count towards the threshold.
+    pub fn from_reports(reports: &[GapReport], threshold: usize) -> Vec<GapAlert> {
+        let mut alerts: Vec<GapAlert> = vec![];
+        let mut missing: Vec<Vec<_>> = vec![];
+        for report in reports.iter().filter(|report| !report.is_failed()) {
+            let index = match alerts.iter().position(|alert| {
+                alert.symbol == report.symbol && alert.resolution == report.resolution
+            }) {
+                Some(index) => index,
+                None => {
+                    alerts.push(GapAlert {
+                        symbol: report.symbol.clone(),
+                        resolution: report.resolution,
+                        expected: 0,
+                        missing: 0,
+                        availability: 1.0,
+                        gaps: vec![],
+                    });
+                    missing.push(vec![]);
+                    alerts.len() - 1
+                }
+            };
+            alerts[index].expected += report.expected;
+            missing[index].extend_from_slice(&report.missing);
+        }
+
+        alerts
+            .into_iter()
+            .zip(missing)
+            .filter(|(_, missing)| missing.len() > threshold)
+            .map(|(mut alert, mut missing)| {
+                missing.sort_unstable();
+                missing.dedup();
+                alert.missing = missing.len();
+                if alert.expected > 0 {
+                    alert.availability = 1.0 - alert.missing as f64 / alert.expected as f64;
+                }
+                alert.gaps = group_gaps(&missing, alert.resolution);
+                alert
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for GapAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {} candle(s) missing at resolution {} ({:.2}% available)",
+            self.symbol,
+            self.missing,
+            self.resolution,
+            self.availability * 100.0
+        )?;
+        for gap in &self.gaps {
+            write!(f, "\n{}", gap)?;
+        }
+        Ok(())
+    }
+}
+
+/// Posts alerts to a webhook. Delivery failures are logged, never returned, so an unreachable
+/// webhook can't take a monitor down with it.
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: Url,
+    format: WebhookFormat,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: Url, format: WebhookFormat) -> Self {
+        Webhook {
+            url,
+            format,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub async fn send(&self, alert: &GapAlert) {
+        let body = match self.format {
+            WebhookFormat::Json => json!(alert),
+            WebhookFormat::Slack => json!({ "text": alert.to_string() }),
+        };
+        let sent = self
+            .client
+            .post(self.url.clone())
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        // Only the host is logged, webhook URLs usually embed a secret token
+        let host = self.url.host_str().unwrap_or_default();
+        match sent {
+            Ok(_) => tracing::debug!("Sent alert for {} to {}", alert.symbol, host),
+            Err(e) => tracing::warn!("Failed to deliver alert to {}: {}", host, e.without_url()),
+        }
+    }
+}
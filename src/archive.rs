@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::file_name_safe;
+use crate::display::{in_zone, DisplayInZone};
+use crate::report::{group_gaps, GapReport};
+use crate::resolution::Resolution;
+use crate::summary::Summary;
+
+/// Run time prefix of archived file names, e.g. `20240512T083000Z`
+const RUN_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Summaries of past runs, kept as JSON files named `<run time>-<symbol>.json` to build a record
+/// of availability over time.
+#[derive(Debug, Clone)]
+pub struct ReportArchive {
+    dir: PathBuf,
+    retention: Option<TimeDelta>,
+}
+
+#[derive(Serialize)]
+struct ArchivedSummary<'a> {
+    run_at: DateTime<Utc>,
+    symbol: &'a str,
+    summary: &'a Summary,
+    gaps: BTreeMap<String, ArchivedGaps>,
+}
+
+/// The parts of an archived run needed to diff a later one against it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchivedRun {
+    pub run_at: DateTime<Utc>,
+    pub symbol: String,
+    /// By resolution. Missing from files archived before gaps were recorded
+    #[serde(default)]
+    pub gaps: BTreeMap<String, ArchivedGaps>,
+}
+
+/// The periods of one resolution that were checked, and the candles missing from them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArchivedGaps {
+    pub periods: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+    pub missing: Vec<DateTime<Utc>>,
+}
+
+impl ArchivedGaps {
+    fn covers(&self, time: DateTime<Utc>) -> bool {
+        self.periods
+            .iter()
+            .any(|(from, to)| (*from..=*to).contains(&time))
+    }
+}
+
+/// Gaps of `reports` by resolution. Failed periods weren't checked, so they're left out.
+fn gaps_of(reports: &[GapReport]) -> BTreeMap<String, ArchivedGaps> {
+    let mut gaps: BTreeMap<String, ArchivedGaps> = BTreeMap::new();
+    for report in reports.iter().filter(|report| !report.is_failed()) {
+        let resolution = gaps.entry(report.resolution.to_string()).or_default();
+        resolution.periods.push((report.from, report.to));
+        resolution.missing.extend(&report.missing);
+    }
+    gaps
+}
+
+/// How the gaps of a symbol and resolution changed since an earlier run. Only candles checked
+/// in both runs are compared, so windows that moved or were sampled differently don't show up
+/// as changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GapDiff {
+    pub symbol: String,
+    pub resolution: Resolution,
+    pub since: DateTime<Utc>,
+    pub newly_missing: Vec<DateTime<Utc>>,
+    pub now_present: Vec<DateTime<Utc>>,
+}
+
+impl GapDiff {
+    pub fn is_empty(&self) -> bool {
+        self.newly_missing.is_empty() && self.now_present.is_empty()
+    }
+}
+
+impl ArchivedRun {
+    /// Diffs `reports`, all of this run's symbol, against the gaps of this run. One diff per
+    /// resolution checked in both runs, in resolution order.
+    pub fn diff(&self, reports: &[GapReport]) -> Vec<GapDiff> {
+        let mut diffs = vec![];
+        for (resolution, current) in gaps_of(reports) {
+            let (Some(previous), Ok(parsed)) =
+                (self.gaps.get(&resolution), resolution.parse::<Resolution>())
+            else {
+                continue;
+            };
+            let was_missing: HashSet<&DateTime<Utc>> = previous.missing.iter().collect();
+            let is_missing: HashSet<&DateTime<Utc>> = current.missing.iter().collect();
+            let mut newly_missing: Vec<DateTime<Utc>> = current
+                .missing
+                .iter()
+                .filter(|time| !was_missing.contains(time) && previous.covers(**time))
+                .copied()
+                .collect();
+            let mut now_present: Vec<DateTime<Utc>> = previous
+                .missing
+                .iter()
+                .filter(|time| !is_missing.contains(time) && current.covers(**time))
+                .copied()
+                .collect();
+            newly_missing.sort_unstable();
+            newly_missing.dedup();
+            now_present.sort_unstable();
+            now_present.dedup();
+            diffs.push(GapDiff {
+                symbol: self.symbol.clone(),
+                resolution: parsed,
+                since: self.run_at,
+                newly_missing,
+                now_present,
+            });
+        }
+        diffs
+    }
+}
+
+impl DisplayInZone for GapDiff {
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, tz: Tz) -> fmt::Result {
+        const FORMAT: &str = "%Y-%m-%d %H:%M";
+        write!(
+            f,
+            "[{}] Changes since the run at {} (resolution {}):",
+            self.symbol,
+            in_zone(self.since, tz).format(FORMAT),
+            self.resolution
+        )?;
+        if self.is_empty() {
+            return write!(f, " none");
+        }
+        for (label, times) in [
+            ("Newly missing", &self.newly_missing),
+            ("Now present", &self.now_present),
+        ] {
+            for range in group_gaps(times, self.resolution) {
+                write!(
+                    f,
+                    "\n[{}] {}: {}",
+                    self.symbol,
+                    label,
+                    in_zone(range.start, tz).format(FORMAT)
+                )?;
+                if range.count > 1 {
+                    write!(
+                        f,
+                        " → {} ({} candles)",
+                        in_zone(range.end, tz).format(FORMAT),
+                        range.count
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReportArchive {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ReportArchive {
+            dir: dir.into(),
+            retention: None,
+        }
+    }
+
+    /// `prune` deletes runs older than `retention`. Without it, every run is kept.
+    pub fn with_retention(mut self, retention: TimeDelta) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Saves the summary of `symbol` for the run started at `run_at`, along with the gaps of
+    /// `reports` for later diffs. It's written to a temporary file first and renamed into
+    /// place, so a partial file never shows up.
+    pub fn save(
+        &self,
+        run_at: DateTime<Utc>,
+        symbol: &str,
+        summary: &Summary,
+        reports: &[GapReport],
+    ) -> anyhow::Result<PathBuf> {
+        let name = format!(
+            "{}-{}.json",
+            run_at.format(RUN_TIME_FORMAT),
+            file_name_safe(symbol)
+        );
+        let path = self.dir.join(&name);
+        let temporary = self.dir.join(format!(".{}.tmp", name));
+        std::fs::create_dir_all(&self.dir)?;
+        let mut json = serde_json::to_string_pretty(&ArchivedSummary {
+            run_at,
+            symbol,
+            summary,
+            gaps: gaps_of(reports),
+        })?;
+        json.push('\n');
+        std::fs::write(&temporary, json)
+            .with_context(|| format!("Failed to write {}", temporary.display()))?;
+        std::fs::rename(&temporary, &path)
+            .with_context(|| format!("Failed to move {} into place", temporary.display()))?;
+        Ok(path)
+    }
+
+    /// The latest run of `symbol` archived before `run_at`, if any.
+    pub fn previous_run(
+        &self,
+        symbol: &str,
+        run_at: DateTime<Utc>,
+    ) -> anyhow::Result<Option<ArchivedRun>> {
+        let file_name = format!("{}.json", file_name_safe(symbol));
+        let mut latest = None;
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let Some((time, rest)) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split_once('-'))
+            else {
+                continue;
+            };
+            let Ok(time) = NaiveDateTime::parse_from_str(time, RUN_TIME_FORMAT) else {
+                continue;
+            };
+            let time = time.and_utc();
+            if rest == file_name
+                && time < run_at
+                && latest.as_ref().is_none_or(|(latest, _)| time > *latest)
+            {
+                latest = Some((time, path));
+            }
+        }
+        let Some((_, path)) = latest else {
+            return Ok(None);
+        };
+        let bytes = std::fs::read(&path)?;
+        let run = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Malformed archived run {}", path.display()))?;
+        Ok(Some(run))
+    }
+
+    /// Deletes the files of runs started more than the retention before `now`, and returns how
+    /// many. Files not named like archived runs are left alone.
+    pub fn prune(&self, now: DateTime<Utc>) -> anyhow::Result<usize> {
+        let Some(retention) = self.retention else {
+            return Ok(0);
+        };
+        let mut pruned = 0;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let run_at = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .filter(|name| name.ends_with(".json"))
+                .and_then(|name| name.split_once('-'))
+                .and_then(|(run_at, _)| {
+                    NaiveDateTime::parse_from_str(run_at, RUN_TIME_FORMAT).ok()
+                });
+            if run_at.is_some_and(|run_at| run_at.and_utc() < now - retention) {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to delete {}", path.display()))?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use futures::{stream, StreamExt};
+use serde::Serialize;
+
+use crate::client::ApiClient;
+use crate::history::STATUS_ERROR;
+use crate::latency::LatencyStats;
+use crate::resolution::Resolution;
+
+/// Outcome of firing the same `history` request repeatedly. Nothing is checked for gaps: a
+/// request counts as successful when it returns a well-formed response that isn't an `error`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub requests: usize,
+    pub errors: usize,
+    pub concurrency: usize,
+    /// Wall-clock time from the first request to the last response, in seconds
+    #[serde(serialize_with = "as_secs")]
+    pub elapsed: Duration,
+    /// Completed requests per second, failed ones included
+    pub throughput: f64,
+    /// Of the successful requests only
+    pub latency: Option<LatencyStats>,
+}
+
+impl BenchReport {
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        self.errors as f64 / self.requests as f64
+    }
+}
+
+/// Sends `requests` requests for `[from, to]`, at most `concurrency` at a time. Goes through the
+/// client's rate limiter and retries, so the throughput is what a real run would achieve with
+/// the same settings.
+pub async fn run_bench(
+    client: &ApiClient,
+    symbol: &str,
+    resolution: Resolution,
+    from: i64,
+    to: i64,
+    requests: usize,
+    concurrency: usize,
+) -> BenchReport {
+    // Only this run's requests should count
+    client.take_latency_stats();
+    let start = Instant::now();
+    let errors = stream::iter(0..requests)
+        .map(|_| client.fetch_raw_history(symbol, resolution, from, to))
+        .buffer_unordered(concurrency)
+        .filter(|result| {
+            let failed = match result {
+                Ok(result) => result.s == STATUS_ERROR,
+                Err(e) => {
+                    tracing::debug!("[{}] Benchmark request failed: {:#}", symbol, e);
+                    true
+                }
+            };
+            futures::future::ready(failed)
+        })
+        .count()
+        .await;
+    let elapsed = start.elapsed();
+
+    BenchReport {
+        requests,
+        errors,
+        concurrency,
+        elapsed,
+        throughput: requests as f64 / elapsed.as_secs_f64(),
+        latency: client.take_latency_stats(),
+    }
+}
+
+fn as_secs<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>8}  {:>11}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}  {:>10}",
+            "requests",
+            "concurrency",
+            "error rate",
+            "elapsed",
+            "req/s",
+            "min",
+            "median",
+            "p95",
+            "p99"
+        )?;
+        let latency = |pick: fn(&LatencyStats) -> Duration| {
+            self.latency
+                .as_ref()
+                .map_or("-".to_string(), |latency| format!("{:.1?}", pick(latency)))
+        };
+        write!(
+            f,
+            "{:>8}  {:>11}  {:>9.2}%  {:>10}  {:>10.1}  {:>10}  {:>10}  {:>10}  {:>10}",
+            self.requests,
+            self.concurrency,
+            self.error_rate() * 100.0,
+            format!("{:.2?}", self.elapsed),
+            self.throughput,
+            latency(|latency| latency.min),
+            latency(|latency| latency.median),
+            latency(|latency| latency.p95),
+            latency(|latency| latency.p99)
+        )
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use url::Url;
+
+use crate::history::ApiResult;
+use crate::resolution::Resolution;
+
+/// Raw `history` responses stored as JSON files, one per `(endpoint, symbol, resolution, from,
+/// to)`. Entries older than `ttl` are ignored. Cache failures are logged and otherwise treated
+/// as a miss, they never fail a request.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    refresh: bool,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        ResponseCache {
+            dir: dir.into(),
+            ttl,
+            refresh: false,
+        }
+    }
+
+    /// Ignore existing entries, but still store fresh responses.
+    pub fn with_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn get(
+        &self,
+        base_url: &Url,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> Option<ApiResult> {
+        if self.refresh {
+            return None;
+        }
+        let path = self.path_for(base_url, symbol, resolution, from, to);
+        let age = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+        if age > self.ttl {
+            tracing::debug!("Cache entry {} expired", path.display());
+            return None;
+        }
+
+        match std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(serde_json::from_slice(&bytes)?))
+        {
+            Ok(result) => {
+                tracing::debug!("Cache hit: {}", path.display());
+                Some(result)
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable cache entry {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn put(
+        &self,
+        base_url: &Url,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+        result: &ApiResult,
+    ) {
+        let path = self.path_for(base_url, symbol, resolution, from, to);
+        let written = std::fs::create_dir_all(&self.dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(std::fs::write(&path, serde_json::to_vec(result)?)?));
+        if let Err(e) = written {
+            tracing::warn!("Failed to write cache entry {}: {}", path.display(), e);
+        }
+    }
+
+    /// The symbol is kept readable in the file name; the endpoint only contributes a hash.
+    fn path_for(
+        &self,
+        base_url: &Url,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        base_url.as_str().hash(&mut hasher);
+        self.dir.join(format!(
+            "{}-{:016x}.json",
+            request_file_stem(symbol, resolution, from, to),
+            hasher.finish()
+        ))
+    }
+}
+
+/// File name friendly `symbol-resolution-from-to`.
+pub(crate) fn request_file_stem(
+    symbol: &str,
+    resolution: Resolution,
+    from: i64,
+    to: i64,
+) -> String {
+    format!("{}-{}-{}-{}", file_name_safe(symbol), resolution, from, to)
+}
+
+/// `symbol` with anything but ASCII letters and digits replaced by `_`.
+pub(crate) fn file_name_safe(symbol: &str) -> String {
+    symbol
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeDelta, Utc, Weekday};
+use serde::Deserialize;
+
+use crate::resolution::Resolution;
+
+/// Trading sessions for markets that don't trade around the clock. Candles outside a session
+/// or on a holiday aren't expected. All times are UTC.
+///
+/// Loaded from JSON such as:
+///
+/// ```json
+/// {
+///   "sessions": {
+///     "Mon": [{ "open": "14:30:00", "close": "21:00:00" }],
+///     "Tue": [{ "open": "14:30:00", "close": "21:00:00" }]
+///   },
+///   "holidays": ["2024-12-25"]
+/// }
+/// ```
+///
+/// Weekdays without sessions are closed. A session with `close` at or before `open` runs past
+/// midnight into the next day.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TradingCalendar {
+    pub sessions: HashMap<Weekday, Vec<Session>>,
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Session {
+    pub open: NaiveTime,
+    pub close: NaiveTime,
+}
+
+impl TradingCalendar {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read trading calendar {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Malformed trading calendar {}", path.display()))
+    }
+
+    /// Whether a candle starting at `time` is expected. Intraday candles have to overlap a
+    /// session, daily candles need a session on their day. Weekly and monthly candles are
+    /// always expected.
+    pub fn is_open(&self, time: DateTime<Utc>, resolution: Resolution) -> bool {
+        match resolution {
+            Resolution::Minutes(_) | Resolution::Hours(_) => {
+                let end = time + resolution.approximate_duration();
+                // The day before is included for sessions that run past midnight
+                let first_day = time.date_naive().pred_opt().unwrap();
+                first_day
+                    .iter_days()
+                    .take_while(|date| date.and_time(NaiveTime::MIN).and_utc() < end)
+                    .any(|date| {
+                        self.sessions_on(date).iter().any(|session| {
+                            let open = date.and_time(session.open).and_utc();
+                            let mut close = date.and_time(session.close).and_utc();
+                            if session.close <= session.open {
+                                close += TimeDelta::days(1);
+                            }
+                            open < end && close > time
+                        })
+                    })
+            }
+            Resolution::Days(_) => !self.sessions_on(time.date_naive()).is_empty(),
+            Resolution::Weeks(_) | Resolution::Months(_) => true,
+        }
+    }
+
+    fn sessions_on(&self, date: NaiveDate) -> &[Session] {
+        if self.holidays.contains(&date) {
+            return &[];
+        }
+        self.sessions
+            .get(&date.weekday())
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+}
@@ -0,0 +1,1358 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc, Weekday};
+use reqwest::header::HeaderMap;
+use url::Url;
+
+use crate::adapter::{ResponseAdapter, UdfAdapter};
+use crate::cache::ResponseCache;
+use crate::calendar::TradingCalendar;
+use crate::compare::{ComparisonReport, Tolerance};
+use crate::datafeed_config::DatafeedConfig;
+use crate::error_category::{ErrorCategory, ErrorCounts};
+use crate::fixtures::Fixtures;
+use crate::history::{
+    ApiResult, StructuredApiResult, Time, STATUS_ERROR, STATUS_NO_DATA, STATUS_OK,
+};
+use crate::http_trace::HttpTrace;
+use crate::latency::LatencyStats;
+use crate::metrics::Metrics;
+use crate::rate_limit::{RateLimiter, RequestBudget, RequestBudgetExhausted};
+use crate::report::{GapReport, PeriodStatus};
+use crate::resolution::{candle_times, last_normalized_time_at_or_before, Resolution};
+use crate::retry::RetryPolicy;
+use crate::symbol_info::SymbolInfo;
+use crate::validation::AnomalyKind;
+
+/// Returning this many times more candles than expected usually means the API answered with a
+/// different resolution, or repeats timestamps.
+const EXCESS_CANDLES_RATIO: f64 = 1.5;
+
+/// Correlation id attached to the span of every `history` request.
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// HTTP versions a client may use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/2 when the server offers it during the TLS handshake (ALPN), HTTP/1.1 otherwise,
+    /// including over plain HTTP
+    #[default]
+    Auto,
+    Http1,
+    /// HTTP/2 without negotiation, for servers known to speak it, e.g. over plain HTTP
+    Http2,
+}
+
+impl HttpVersion {
+    pub fn configure(self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        match self {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        }
+    }
+}
+
+/// Client for a UDF-compatible datafeed. A single `reqwest::Client` is shared by every request
+/// and by clones, so idle connections are kept in its pool and reused: only the first request
+/// to a host pays for the TCP and TLS handshakes.
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    base_url: Url,
+    client: reqwest::Client,
+    week_start: Weekday,
+    alignment_offset: TimeDelta,
+    retry_policy: RetryPolicy,
+    validate_ohlc: bool,
+    flat_run_threshold: Option<usize>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    max_bars: Option<usize>,
+    follow_next_time: bool,
+    countback: bool,
+    exclude_current_candle: bool,
+    cache: Option<ResponseCache>,
+    fixtures: Option<Fixtures>,
+    calendar: Option<Arc<TradingCalendar>>,
+    volume_z_threshold: Option<f64>,
+    metrics: Option<Metrics>,
+    headers: HeaderMap,
+    http_trace: Option<HttpTrace>,
+    response_adapter: Arc<dyn ResponseAdapter>,
+    /// Shared by clones, so concurrent checks all record into the same samples. Like the other
+    /// shared maps, recovered when poisoned, so a symbol whose check panicked can't fail the rest
+    latencies: Arc<Mutex<Vec<Duration>>>,
+    /// Also shared by clones
+    errors: Arc<Mutex<ErrorCounts>>,
+    request_budget: Option<RequestBudget>,
+    /// Mirrors that check a period when this endpoint can't, in order
+    fallbacks: Vec<ApiClient>,
+    /// Fetched at most once per run, and shared by clones
+    datafeed_config: Arc<OnceLock<DatafeedConfig>>,
+    /// Earliest candle found by `detect_first_candle` per symbol, shared by clones
+    first_candles: Arc<Mutex<HashMap<String, Time>>>,
+    /// Newest candle per symbol, from `detect_last_candle` or `record_last_candle`, shared by
+    /// clones
+    last_candles: Arc<Mutex<HashMap<String, Time>>>,
+    /// HTTP version of the first response, shared by clones
+    negotiated_version: Arc<OnceLock<reqwest::Version>>,
+}
+
+impl ApiClient {
+    /// Endpoints are resolved relative to `base_url`, which can point at a production datafeed
+    /// as well as a local mock server. A missing trailing slash is added, so `https://x.com/api`
+    /// and `https://x.com/api/` are equivalent.
+    pub fn new(base_url: Url) -> Self {
+        let client = reqwest::Client::builder()
+            .connect_timeout(Self::DEFAULT_CONNECT_TIMEOUT)
+            .timeout(Self::DEFAULT_REQUEST_TIMEOUT)
+            .pool_idle_timeout(Self::DEFAULT_POOL_IDLE_TIMEOUT)
+            .pool_max_idle_per_host(Self::DEFAULT_POOL_MAX_IDLE_PER_HOST)
+            .build()
+            .expect("Failed to initialize the HTTP client");
+        ApiClient {
+            base_url: normalize_base_url(base_url),
+            client,
+            week_start: Weekday::Mon,
+            alignment_offset: TimeDelta::zero(),
+            retry_policy: RetryPolicy::default(),
+            validate_ohlc: false,
+            flat_run_threshold: None,
+            rate_limiter: None,
+            max_bars: None,
+            follow_next_time: false,
+            countback: false,
+            exclude_current_candle: false,
+            cache: None,
+            fixtures: None,
+            calendar: None,
+            volume_z_threshold: None,
+            metrics: None,
+            headers: HeaderMap::new(),
+            http_trace: None,
+            response_adapter: Arc::new(UdfAdapter::default()),
+            latencies: Arc::default(),
+            errors: Arc::default(),
+            request_budget: None,
+            fallbacks: vec![],
+            datafeed_config: Arc::default(),
+            first_candles: Arc::default(),
+            last_candles: Arc::default(),
+            negotiated_version: Arc::default(),
+        }
+    }
+
+    pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+    pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+    /// Idle pooled connections are closed after this long
+    pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+    pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 16;
+
+    /// HTTP version the endpoint answered the first request with, once there was one.
+    pub fn negotiated_version(&self) -> Option<reqwest::Version> {
+        self.negotiated_version.get().copied()
+    }
+
+    /// Replaces the HTTP client, e.g. to use different timeouts. Timed out requests are retried
+    /// like connection errors.
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Day of the week weekly candles are aligned to. Defaults to Monday.
+    pub fn with_week_start(mut self, week_start: Weekday) -> Self {
+        self.week_start = week_start;
+        self
+    }
+
+    /// Shifts every candle time by `offset`, for providers that don't align candles to the top
+    /// of the hour or midnight UTC, e.g. daily candles at a 22:00 UTC market open.
+    pub fn with_alignment_offset(mut self, offset: TimeDelta) -> Self {
+        self.alignment_offset = offset;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Flag candles that violate OHLC invariants in every report. Off by default since some
+    /// feeds legitimately emit zero-volume candles.
+    pub fn with_ohlc_validation(mut self, validate_ohlc: bool) -> Self {
+        self.validate_ohlc = validate_ohlc;
+        self
+    }
+
+    /// Caps the number of requests started per second across all clones of this client.
+    pub fn with_rate_limit(mut self, requests_per_second: f64) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(requests_per_second)));
+        self
+    }
+
+    /// Largest number of candles requested at once. Longer periods are split into several
+    /// requests, since many datafeeds silently truncate oversized responses.
+    pub fn with_max_bars(mut self, max_bars: usize) -> Self {
+        assert!(max_bars > 0, "max_bars must be positive");
+        self.max_bars = Some(max_bars);
+        self
+    }
+
+    /// When a request returns `no_data` with a `nextTime` inside the requested window, re-issue
+    /// it starting at `nextTime`. This tells "no data at all" apart from "data starts later".
+    pub fn with_next_time_following(mut self, follow_next_time: bool) -> Self {
+        self.follow_next_time = follow_next_time;
+        self
+    }
+
+    /// Ask for the last `N` candles up to `to` with `countback=N`, where `N` is the number of
+    /// candles expected in the period, instead of relying on `from` alone. Some datafeeds only
+    /// honour `countback` reliably. The checked window then starts at the earliest candle
+    /// returned if that's before `from`.
+    pub fn with_countback(mut self, countback: bool) -> Self {
+        self.countback = countback;
+        self
+    }
+
+    /// Don't expect candles that haven't closed yet when the period is checked. The one still
+    /// forming at the end of a period reaching up to now is often absent or partial, which
+    /// isn't a gap.
+    pub fn with_current_candle_excluded(mut self, exclude: bool) -> Self {
+        self.exclude_current_candle = exclude;
+        self
+    }
+
+    /// Flag runs of at least `min_candles` consecutive flat candles (open, high, low and close
+    /// equal) at the same price as suspected filler.
+    pub fn with_flat_candle_detection(mut self, min_candles: usize) -> Self {
+        assert!(min_candles > 0, "min_candles must be positive");
+        self.flat_run_threshold = Some(min_candles);
+        self
+    }
+
+    /// Serve repeated requests from an on-disk cache instead of the network.
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Record responses to fixture files, or replay them without touching the network.
+    pub fn with_fixtures(mut self, fixtures: Fixtures) -> Self {
+        self.fixtures = Some(fixtures);
+        self
+    }
+
+    /// Only expect candles during the calendar's trading sessions. Without a calendar every
+    /// candle is expected, which suits 24/7 markets.
+    pub fn with_calendar(mut self, calendar: TradingCalendar) -> Self {
+        self.calendar = Some(Arc::new(calendar));
+        self
+    }
+
+    /// Add volume statistics to every report, flagging candles whose volume is more than
+    /// `z_threshold` standard deviations above the period's mean.
+    pub fn with_volume_stats(mut self, z_threshold: f64) -> Self {
+        self.volume_z_threshold = Some(z_threshold);
+        self
+    }
+
+    /// Record the latency of every request sent over the network.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Send these headers with every request, e.g. an API key. Their values are marked
+    /// sensitive, so they never show up in `Debug` output or logs.
+    pub fn with_headers(mut self, mut headers: HeaderMap) -> Self {
+        for value in headers.values_mut() {
+            value.set_sensitive(true);
+        }
+        self.headers = headers;
+        self
+    }
+
+    /// Logs every request and response in full, see [`HttpTrace`].
+    pub fn with_http_trace(mut self, trace: HttpTrace) -> Self {
+        self.http_trace = Some(trace);
+        self
+    }
+
+    /// Parses `history` responses with `adapter`, for providers that don't use the UDF layout.
+    pub fn with_response_adapter(mut self, adapter: Arc<dyn ResponseAdapter>) -> Self {
+        self.response_adapter = adapter;
+        self
+    }
+
+    /// When a period still fails after this endpoint's retries, check it with each of
+    /// `fallbacks` in turn until one succeeds. They keep their own retry policy and rate limit,
+    /// while their latencies and errors are recorded with this client's. Periods checked this
+    /// way report the endpoint that served them.
+    pub fn with_fallbacks(mut self, fallbacks: Vec<ApiClient>) -> Self {
+        self.fallbacks = fallbacks
+            .into_iter()
+            .map(|mut fallback| {
+                fallback.latencies = self.latencies.clone();
+                fallback.errors = self.errors.clone();
+                fallback
+            })
+            .collect();
+        self
+    }
+
+    /// Take every request, retries included, out of `budget`. Once it's used up, requests fail
+    /// with `RequestBudgetExhausted` without being sent.
+    pub fn with_request_budget(mut self, budget: RequestBudget) -> Self {
+        self.request_budget = Some(budget);
+        self
+    }
+
+    pub fn request_budget(&self) -> Option<&RequestBudget> {
+        self.request_budget.as_ref()
+    }
+
+    pub fn request_budget_exhausted(&self) -> bool {
+        self.request_budget
+            .as_ref()
+            .is_some_and(RequestBudget::is_exhausted)
+    }
+
+    /// Latency of the requests sent since the last call. Requests served from the cache or
+    /// fixtures aren't counted.
+    pub fn take_latency_stats(&self) -> Option<LatencyStats> {
+        LatencyStats::from_samples(std::mem::take(
+            &mut *self
+                .latencies
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        ))
+    }
+
+    /// Failed request attempts since the last call, by cause.
+    pub fn take_error_counts(&self) -> ErrorCounts {
+        std::mem::take(&mut *self.errors.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn count_error(&self, category: ErrorCategory) {
+        self.errors
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .add(category);
+    }
+
+    pub fn week_start(&self) -> Weekday {
+        self.week_start
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// Candle times in `[from, to]` that should be present, honouring the week start and the
+    /// trading calendar. Long windows at fine resolutions are better walked with
+    /// `iter_expected_candle_times`.
+    pub fn expected_candle_times(
+        &self,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Vec<DateTime<Utc>> {
+        self.iter_expected_candle_times(resolution, from, to)
+            .collect()
+    }
+
+    /// Length of `expected_candle_times`, without collecting the times.
+    pub fn expected_candle_count(
+        &self,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> usize {
+        self.iter_expected_candle_times(resolution, from, to)
+            .count()
+    }
+
+    /// The times of `expected_candle_times` in ascending order, computed as they're iterated.
+    pub fn iter_expected_candle_times(
+        &self,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> impl Iterator<Item = DateTime<Utc>> + '_ {
+        let offset = self.alignment_offset;
+        candle_times(from - offset, to - offset, resolution, self.week_start)
+            .map(move |time| time + offset)
+            .filter(move |time| {
+                self.calendar
+                    .as_ref()
+                    .is_none_or(|calendar| calendar.is_open(*time, resolution))
+            })
+    }
+
+    /// The candle times `report` was checked against, without the still-forming candles it
+    /// excluded.
+    pub fn checked_candle_times<'a>(
+        &'a self,
+        report: &GapReport,
+    ) -> impl Iterator<Item = DateTime<Utc>> + 'a {
+        // The excluded candles are the last ones
+        self.iter_expected_candle_times(report.resolution, report.from, report.to)
+            .take(report.expected)
+    }
+
+    /// ${BASE_URL}history?symbol=${symbolInfo.name}&resolution=${apiResolution}&from=${from}&to=${to}
+    ///
+    /// With countback enabled, `&countback=${countback}` is appended.
+    pub fn history_url(
+        &self,
+        symbol: &str,
+        api_resolution: Resolution,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Url {
+        let mut url = self.endpoint_url("history");
+        url.query_pairs_mut()
+            .append_pair("symbol", symbol)
+            .append_pair("resolution", &api_resolution.to_string())
+            .append_pair("from", &from_ts.to_string())
+            .append_pair("to", &to_ts.to_string());
+        if self.countback {
+            url.query_pairs_mut().append_pair(
+                "countback",
+                &self
+                    .countback_for(api_resolution, from_ts, to_ts)
+                    .to_string(),
+            );
+        }
+        url
+    }
+
+    /// Number of candles to ask for when requesting `[from, to]` with `countback`.
+    fn countback_for(&self, resolution: Resolution, from: i64, to: i64) -> usize {
+        self.expected_candle_count(
+            resolution,
+            DateTime::from_timestamp(from, 0).unwrap(),
+            DateTime::from_timestamp(to, 0).unwrap(),
+        )
+    }
+
+    /// The datafeed's `config` endpoint. Only the first call sends a request, later ones return
+    /// the same config.
+    pub async fn fetch_datafeed_config(&self) -> anyhow::Result<DatafeedConfig> {
+        if let Some(config) = self.datafeed_config.get() {
+            return Ok(config.clone());
+        }
+        let url = self.endpoint_url("config");
+        tracing::debug!("Request url: {}", url);
+
+        let (response, _) = self.get_with_retries(&url).await?;
+        let body = self.read_body(response).await?;
+        let config: DatafeedConfig = serde_json::from_str(&body)
+            .with_context(|| format!("Malformed datafeed config: {}", body_snippet(&body)))?;
+        Ok(self.datafeed_config.get_or_init(|| config).clone())
+    }
+
+    /// Fetches the symbol's metadata from the UDF `symbols` endpoint. Fails if the datafeed
+    /// doesn't know the symbol.
+    pub async fn fetch_symbol_info(&self, symbol: &str) -> anyhow::Result<SymbolInfo> {
+        let mut url = self.endpoint_url("symbols");
+        url.query_pairs_mut().append_pair("symbol", symbol);
+        tracing::debug!("Request url: {}", url);
+
+        let (response, _) = self.get_with_retries(&url).await?;
+        let body = self.read_body(response).await?;
+        let info: SymbolInfo = serde_json::from_str(&body)
+            .with_context(|| format!("Malformed symbol info: {}", body_snippet(&body)))?;
+        if info.is_error() {
+            anyhow::bail!(
+                "Unknown symbol {}: {}",
+                symbol,
+                info.errmsg.as_deref().unwrap_or("no error message")
+            );
+        }
+        Ok(info)
+    }
+
+    /// Finds the time of the symbol's earliest candle within `[from, to]`, e.g. when it was
+    /// listed, by binary search: a `no_data` answer up to `t` means it's after `t`, and a
+    /// `nextTime` hint moves the search straight there. `None` if there are no candles at all.
+    ///
+    /// Found times are remembered for the rest of the run, so later calls for the same symbol
+    /// send no requests.
+    pub async fn detect_first_candle(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Option<Time>> {
+        if let Some(first) = self
+            .first_candles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(symbol)
+        {
+            return Ok(Some((*first).max(from)));
+        }
+
+        let step = resolution.approximate_duration().num_seconds();
+        // There are no candles up to `before`, and none before `first`
+        let mut before = from - 1;
+        let mut first = None;
+        let mut upper = to;
+        let mut probes = 0;
+        while upper - before > step {
+            probes += 1;
+            let probe = before + (upper - before) / 2;
+            let result = self
+                .fetch_raw_history(symbol, resolution, before + 1, probe)
+                .await?;
+            match result.s.as_str() {
+                STATUS_OK if !result.time.is_empty() => {
+                    let earliest = *result.time.iter().min().unwrap();
+                    first = Some(earliest);
+                    upper = earliest;
+                }
+                STATUS_OK | STATUS_NO_DATA => {
+                    before = match result.next_time {
+                        Some(next_time) if next_time > probe && next_time <= upper => next_time - 1,
+                        _ => probe,
+                    };
+                }
+                _ => anyhow::bail!(
+                    "Failed to probe {} for its first candle: {}",
+                    symbol,
+                    result.errmsg.as_deref().unwrap_or("no error message")
+                ),
+            }
+        }
+        // Only the last slot is left unprobed
+        if first.is_none() {
+            let result = self
+                .fetch_raw_history(symbol, resolution, before + 1, to)
+                .await?;
+            first = result.time.iter().min().copied();
+        }
+
+        if let Some(first) = first {
+            tracing::info!(
+                "[{}] First candle at {} ({} probing request(s))",
+                symbol,
+                DateTime::from_timestamp(first, 0).unwrap(),
+                probes
+            );
+            self.first_candles
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(symbol.to_string(), first);
+        }
+        Ok(first)
+    }
+
+    /// Finds the time of the symbol's newest candle within `[from, to]`, looking back from `to`
+    /// over spans that double until one has candles. `None` if there are no candles at all.
+    ///
+    /// A candle remembered from an earlier call, or by `record_last_candle`, is returned without
+    /// sending any request.
+    pub async fn detect_last_candle(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Option<Time>> {
+        if let Some(last) = self
+            .last_candles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(symbol)
+        {
+            if (from..=to).contains(last) {
+                return Ok(Some(*last));
+            }
+        }
+
+        let step = resolution.approximate_duration().num_seconds();
+        let max_span = self
+            .max_bars
+            .map_or(i64::MAX, |max_bars| max_bars as i64 * step);
+        let mut span = step * 16;
+        let mut upper = to;
+        let mut probes = 0;
+        while upper >= from {
+            probes += 1;
+            let lower = upper.saturating_sub(span - 1).max(from);
+            let result = self
+                .fetch_raw_history(symbol, resolution, lower, upper)
+                .await?;
+            match result.s.as_str() {
+                STATUS_OK | STATUS_NO_DATA => {}
+                _ => anyhow::bail!(
+                    "Failed to probe {} for its last candle: {}",
+                    symbol,
+                    result.errmsg.as_deref().unwrap_or("no error message")
+                ),
+            }
+            if let Some(last) = result.time.iter().filter(|time| **time <= to).max() {
+                tracing::info!(
+                    "[{}] Newest candle at {} ({} probing request(s))",
+                    symbol,
+                    DateTime::from_timestamp(*last, 0).unwrap(),
+                    probes
+                );
+                self.record_last_candle(symbol, *last);
+                return Ok(Some(*last));
+            }
+            upper = lower - 1;
+            span = span.saturating_mul(2).min(max_span);
+        }
+        Ok(None)
+    }
+
+    /// Remembers `time` as the newest candle of `symbol`, for later `detect_last_candle` calls.
+    pub fn record_last_candle(&self, symbol: &str, time: Time) {
+        self.last_candles
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(symbol.to_string(), time);
+    }
+
+    fn endpoint_url(&self, endpoint: &str) -> Url {
+        // Can't fail: the base is a valid URL and `endpoint` is a plain relative path
+        self.base_url.join(endpoint).unwrap()
+    }
+
+    #[tracing::instrument(
+        name = "request",
+        skip_all,
+        fields(request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed), from = from, to = to)
+    )]
+    pub async fn fetch_raw_history(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<ApiResult> {
+        if let Some(fixtures @ Fixtures::Replay(_)) = &self.fixtures {
+            return fixtures.load(symbol, resolution, from, to);
+        }
+        if let Some(result) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(&self.base_url, symbol, resolution, from, to))
+        {
+            return Ok(result);
+        }
+
+        let url = self.history_url(symbol, resolution, from, to);
+        tracing::debug!("Request url: {}", url);
+
+        let (response, sent) = self.get_with_retries(&url).await?;
+        let body = self
+            .read_body(response)
+            .await
+            .inspect_err(|e| self.count_error(ErrorCategory::of_reqwest(e)))?;
+        let result = self
+            .response_adapter
+            .parse(&body)
+            .inspect_err(|_| self.count_error(ErrorCategory::Parse))
+            .with_context(|| format!("Malformed response: {}", body_snippet(&body)))?;
+        let latency = sent.elapsed();
+        self.latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(latency);
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_request(symbol, resolution, latency);
+        }
+        // Errors are usually transient, so they're not worth remembering
+        if let Some(cache) = self.cache.as_ref().filter(|_| result.s != STATUS_ERROR) {
+            cache.put(&self.base_url, symbol, resolution, from, to, &result);
+        }
+        if let Some(fixtures @ Fixtures::Record(_)) = &self.fixtures {
+            fixtures.save(symbol, resolution, from, to, &result)?;
+        }
+        Ok(result)
+    }
+
+    /// Retries connection errors, timeouts, 429 and 5xx responses according to the retry policy.
+    /// Any other failure, including other 4xx responses, is returned immediately.
+    ///
+    /// Also returns when the successful attempt was sent, so its latency excludes retries and
+    /// rate limiting.
+    async fn get_with_retries(&self, url: &Url) -> anyhow::Result<(reqwest::Response, Instant)> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if let Some(budget) = &self.request_budget {
+                budget.spend()?;
+            }
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire().await;
+            }
+
+            let mut retry_after = None;
+            if let Some(trace) = &self.http_trace {
+                trace.request(url, &self.headers);
+            }
+            let sent = Instant::now();
+            let response = self
+                .client
+                .get(url.clone())
+                .headers(self.headers.clone())
+                .send()
+                .await;
+            if let (Some(trace), Ok(response)) = (&self.http_trace, &response) {
+                trace.response(response);
+            }
+            if let Ok(response) = &response {
+                if self.negotiated_version.set(response.version()).is_ok() {
+                    tracing::info!(
+                        "Connected to {} over {:?}",
+                        self.base_url.host_str().unwrap_or_default(),
+                        response.version()
+                    );
+                }
+            }
+            let category = match &response {
+                Ok(response) => ErrorCategory::of_status(response.status()),
+                Err(e) => Some(ErrorCategory::of_reqwest(e)),
+            };
+            if let Some(category) = category {
+                self.count_error(category);
+            }
+            let error: anyhow::Error = match response {
+                Ok(response) if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    retry_after = parse_retry_after(response.headers());
+                    HttpStatusError::from_response(response).await.into()
+                }
+                Ok(response) if response.status().is_server_error() => {
+                    HttpStatusError::from_response(response).await.into()
+                }
+                Ok(response) if !response.status().is_success() => {
+                    return Err(HttpStatusError::from_response(response).await.into())
+                }
+                Ok(response) => return Ok((response, sent)),
+                Err(e) if e.is_timeout() => anyhow::anyhow!("Request timed out: {}", e),
+                // Transport failures while sending surface as request errors
+                Err(e) if e.is_connect() || e.is_request() => e.into(),
+                Err(e) => return Err(e.into()),
+            };
+
+            if attempt > self.retry_policy.max_retries {
+                return Err(error);
+            }
+            let delay = retry_after.unwrap_or_else(|| self.retry_policy.delay_for(attempt));
+            if let (Some(rate_limiter), Some(retry_after)) = (&self.rate_limiter, retry_after) {
+                rate_limiter.pause_for(retry_after);
+            }
+            tracing::warn!(
+                "Request attempt {}/{} failed: {}. Retrying in {:?}",
+                attempt,
+                self.retry_policy.max_retries + 1,
+                error,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn read_body(&self, response: reqwest::Response) -> reqwest::Result<String> {
+        let body = response.text().await?;
+        if let Some(trace) = &self.http_trace {
+            trace.body(&body);
+        }
+        Ok(body)
+    }
+
+    /// Fetches `[from, to]` in as many requests as needed to stay under the max-bars limit.
+    /// Chunks are requested sequentially and returned in chronological order.
+    pub async fn fetch_raw_history_chunks(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Vec<ApiResult>> {
+        Ok(self
+            .fetch_chunks(symbol, resolution, from, to)
+            .await?
+            .into_iter()
+            .map(|(result, _)| result)
+            .collect())
+    }
+
+    /// Like `fetch_raw_history_chunks`, but also returns the `nextTime` hint that was followed
+    /// for each chunk, if any.
+    async fn fetch_chunks(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<Vec<(ApiResult, Option<Time>)>> {
+        let chunks = self.chunk_period(resolution, from, to);
+        if chunks.len() > 1 {
+            tracing::debug!(
+                "[{}] Splitting {} -> {} into {} requests",
+                symbol,
+                from,
+                to,
+                chunks.len()
+            );
+        }
+
+        let mut results = Vec::with_capacity(chunks.len());
+        for (chunk_from, chunk_to) in chunks {
+            results.push(
+                self.fetch_chunk(symbol, resolution, chunk_from, chunk_to)
+                    .await?,
+            );
+        }
+        Ok(results)
+    }
+
+    async fn fetch_chunk(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<(ApiResult, Option<Time>)> {
+        let result = self.fetch_raw_history(symbol, resolution, from, to).await?;
+        if !self.follow_next_time || result.s != STATUS_NO_DATA {
+            return Ok((result, None));
+        }
+
+        match result.next_time {
+            // Only follow hints that move forward inside the window, anything else can't
+            // contain candles for this request
+            Some(next_time) if next_time > from && next_time < to => {
+                tracing::info!(
+                    "[{}] No data from {}, following nextTime to {}",
+                    symbol,
+                    from,
+                    next_time
+                );
+                let followed = self
+                    .fetch_raw_history(symbol, resolution, next_time, to)
+                    .await?;
+                Ok((followed, Some(next_time)))
+            }
+            _ => Ok((result, None)),
+        }
+    }
+
+    /// Splits `[from, to]` into consecutive windows of at most `max_bars` candles. Both ends of
+    /// a window are included, so each one ends a second before the next starts.
+    pub fn chunk_period(&self, resolution: Resolution, from: i64, to: i64) -> Vec<(i64, i64)> {
+        let Some(max_bars) = self.max_bars else {
+            return vec![(from, to)];
+        };
+        let span = resolution
+            .approximate_duration()
+            .num_seconds()
+            .saturating_mul(max_bars as i64);
+
+        let mut chunks = vec![];
+        let mut chunk_from = from;
+        loop {
+            let chunk_to = to.min(chunk_from.saturating_add(span - 1));
+            chunks.push((chunk_from, chunk_to));
+            if chunk_to >= to {
+                break;
+            }
+            chunk_from = chunk_to + 1;
+        }
+        chunks
+    }
+
+    pub async fn fetch_history(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<StructuredApiResult> {
+        let mut merged = StructuredApiResult(HashMap::new());
+        for result in self
+            .fetch_raw_history_chunks(symbol, resolution, from, to)
+            .await?
+        {
+            for conflict in merged.merge(result.into_structured()?, Tolerance::default()) {
+                tracing::warn!(
+                    "[{}] Chunks disagree on the candle at {}",
+                    symbol,
+                    conflict.time
+                );
+            }
+        }
+        Ok(merged)
+    }
+
+    #[tracing::instrument(
+        name = "period",
+        skip_all,
+        fields(symbol = %symbol, resolution = %resolution, from = from, to = to)
+    )]
+    pub async fn check_period(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<GapReport> {
+        let mut served_by = &self.base_url;
+        let mut result = self.check_period_here(symbol, resolution, from, to).await;
+        for fallback in &self.fallbacks {
+            let error = match &result {
+                // Another endpoint doesn't make more requests available
+                Err(e) if !e.is::<RequestBudgetExhausted>() => e,
+                _ => break,
+            };
+            tracing::warn!(
+                "[{}] {} failed: {:#}. Failing over to {}",
+                symbol,
+                served_by,
+                error,
+                fallback.base_url
+            );
+            served_by = &fallback.base_url;
+            result = fallback
+                .check_period_here(symbol, resolution, from, to)
+                .await;
+        }
+        if self.fallbacks.is_empty() {
+            return result;
+        }
+        result.map(|mut report| {
+            report.served_by = Some(served_by.to_string());
+            report
+        })
+    }
+
+    /// `check_period` against this endpoint only.
+    async fn check_period_here(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<GapReport> {
+        let from_utc = DateTime::from_timestamp(from, 0).unwrap();
+        let to_utc = DateTime::from_timestamp(to, 0).unwrap();
+
+        tracing::info!(
+            "[{}] Getting API results from {} to {}",
+            symbol,
+            from_utc,
+            to_utc
+        );
+        tracing::debug!("Start timestamp = {}. End timestamp = {}", from, to);
+
+        let mut result = StructuredApiResult(HashMap::new());
+        let mut duplicates = vec![];
+        let mut out_of_order = vec![];
+        let mut outside_window = vec![];
+        // Countback is expected to reach back before `from`
+        let earliest_allowed = if self.countback { Time::MIN } else { from };
+        let mut followed_next_times = vec![];
+        let mut all_no_data = true;
+        let mut volume_unavailable = false;
+        let mut returned = 0;
+        for (raw_result, next_time) in self.fetch_chunks(symbol, resolution, from, to).await? {
+            followed_next_times.extend(next_time);
+            // Candles repeated by overlapping chunks only count once
+            returned += raw_result
+                .time
+                .iter()
+                .filter(|time| !result.0.contains_key(time))
+                .count();
+            all_no_data &= raw_result.s == STATUS_NO_DATA;
+            volume_unavailable |= raw_result.s == STATUS_OK && !raw_result.has_volume();
+            duplicates.extend(raw_result.duplicate_candles());
+            out_of_order.extend(raw_result.ordering_violations());
+            outside_window.extend(raw_result.times_outside(earliest_allowed, to));
+            // Conflicts between chunks are as suspicious as duplicates within one response
+            duplicates.extend(result.merge(raw_result.into_structured()?, Tolerance::default()));
+        }
+        let observed_range = result.observed_range();
+        match observed_range {
+            Some((first, last)) => tracing::info!(
+                "[{}] Candles returned from {} to {}, requested {} to {}",
+                symbol,
+                DateTime::from_timestamp(first, 0).unwrap(),
+                DateTime::from_timestamp(last, 0).unwrap(),
+                from_utc,
+                to_utc
+            ),
+            None => tracing::info!("[{}] No results gotten for time period", symbol),
+        }
+
+        // Countback asks for a number of candles rather than a start time, so a gap pushes the
+        // first returned candle before `from`. Check everything that came back.
+        let mut from_utc = from_utc;
+        if self.countback {
+            if let Some((earliest, _)) = observed_range.filter(|(earliest, _)| *earliest < from) {
+                from_utc = DateTime::from_timestamp(earliest, 0).unwrap();
+                tracing::debug!(
+                    "[{}] Countback returned candles from {}, checking from there",
+                    symbol,
+                    from_utc
+                );
+            }
+        }
+
+        // Walked lazily rather than collected, a multi-year window of 1 minute candles has
+        // hundreds of thousands of them
+        let now = Utc::now();
+        let is_complete =
+            |time: &DateTime<Utc>| !self.exclude_current_candle || resolution.advance(*time) <= now;
+        let expected_times = self
+            .iter_expected_candle_times(resolution, from_utc, to_utc)
+            .take_while(is_complete);
+        let mut report = GapReport::from_result(
+            symbol,
+            resolution,
+            expected_times,
+            from_utc,
+            to_utc,
+            &result,
+        );
+        if self.exclude_current_candle {
+            report.excluded_current = self
+                .iter_expected_candle_times(resolution, from_utc, to_utc)
+                .skip(report.expected)
+                .count();
+        }
+
+        tracing::info!(
+            "[{}] {} candle(s) returned, {} expected",
+            symbol,
+            returned,
+            report.expected
+        );
+        if returned as f64 > report.expected as f64 * EXCESS_CANDLES_RATIO {
+            tracing::warn!(
+                "[{}] Got {} candle(s) where {} were expected, check the resolution and look \
+                 for duplicate timestamps",
+                symbol,
+                returned,
+                report.expected
+            );
+        }
+
+        if let Some((first, _)) = observed_range.filter(|_| report.present == 0) {
+            let first = DateTime::from_timestamp(first, 0).unwrap();
+            let offset = self.alignment_offset;
+            let start =
+                last_normalized_time_at_or_before(first - offset, resolution, self.week_start)
+                    + offset;
+            if first != start {
+                tracing::warn!(
+                    "[{}] None of the returned candles are at an expected time. The first one is \
+                     {} minute(s) past a candle boundary, the provider may align candles with \
+                     an offset",
+                    symbol,
+                    (first - start).num_minutes()
+                );
+            }
+        }
+
+        report.returned = returned;
+        report.duplicates = duplicates;
+        report.out_of_order = out_of_order;
+        report.outside_window = outside_window;
+        report.followed_next_times = followed_next_times;
+        if self.countback {
+            report.countback = Some(self.countback_for(resolution, from, to));
+        }
+        if all_no_data {
+            report.status = PeriodStatus::NoData;
+        }
+        report.volume_unavailable = volume_unavailable;
+        if self.validate_ohlc {
+            report.anomalies = result.anomalies();
+            if volume_unavailable {
+                // The zero volumes are made up
+                report
+                    .anomalies
+                    .retain(|(_, kind)| *kind != AnomalyKind::ZeroVolume);
+            }
+        }
+        if let Some(min_candles) = self.flat_run_threshold {
+            report.suspected_filler = result.flat_runs(min_candles);
+        }
+        if let Some(z_threshold) = self.volume_z_threshold.filter(|_| !volume_unavailable) {
+            report.volume = result.volume_stats(z_threshold);
+        }
+
+        Ok(report)
+    }
+
+    /// Fetches the same period from this client's endpoint ("left") and `other`'s ("right")
+    /// and diffs the candles.
+    #[tracing::instrument(
+        name = "comparison",
+        skip_all,
+        fields(symbol = %symbol, resolution = %resolution, from = from, to = to)
+    )]
+    pub async fn compare_period(
+        &self,
+        other: &ApiClient,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+        tolerance: Tolerance,
+    ) -> anyhow::Result<ComparisonReport> {
+        tracing::info!(
+            "[{}] Comparing {} and {} from {} to {}",
+            symbol,
+            self.base_url,
+            other.base_url,
+            from,
+            to
+        );
+        let (left, right) = futures::try_join!(
+            self.fetch_history(symbol, resolution, from, to),
+            other.fetch_history(symbol, resolution, from, to)
+        )?;
+
+        let mut report = ComparisonReport::compare(
+            symbol,
+            resolution,
+            DateTime::from_timestamp(from, 0).unwrap(),
+            DateTime::from_timestamp(to, 0).unwrap(),
+            &left,
+            &right,
+            tolerance,
+        );
+        report.left = self.base_url.to_string();
+        report.right = other.base_url.to_string();
+        Ok(report)
+    }
+
+    /// Fetches `[from, to]` at both resolutions and checks that aggregating the `fine` candles
+    /// reproduces the `coarse` ones. Only `coarse` candles that lie entirely within the period
+    /// and whose `fine` candles were all returned are compared. The aggregated candles are
+    /// reported as "left".
+    #[tracing::instrument(
+        name = "cross_check",
+        skip_all,
+        fields(symbol = %symbol, fine = %fine, coarse = %coarse, from = from, to = to)
+    )]
+    pub async fn cross_check_period(
+        &self,
+        symbol: &str,
+        fine: Resolution,
+        coarse: Resolution,
+        from: i64,
+        to: i64,
+        tolerance: Tolerance,
+    ) -> anyhow::Result<ComparisonReport> {
+        let from_utc = DateTime::from_timestamp(from, 0).unwrap();
+        let to_utc = DateTime::from_timestamp(to, 0).unwrap();
+        tracing::info!(
+            "[{}] Aggregating resolution {} into {} from {} to {}",
+            symbol,
+            fine,
+            coarse,
+            from_utc,
+            to_utc
+        );
+        let (fine_result, coarse_result) = futures::try_join!(
+            self.fetch_history(symbol, fine, from, to),
+            self.fetch_history(symbol, coarse, from, to)
+        )?;
+
+        let buckets: Vec<(Time, Vec<Time>)> = self
+            .expected_candle_times(coarse, from_utc, to_utc)
+            .into_iter()
+            .map(|start| (start, coarse.advance(start) - TimeDelta::seconds(1)))
+            .filter(|(_, end)| *end <= to_utc)
+            .map(|(start, end)| {
+                let times = self
+                    .expected_candle_times(fine, start, end)
+                    .iter()
+                    .map(DateTime::timestamp)
+                    .collect();
+                (start.timestamp(), times)
+            })
+            .collect();
+        let (aggregated, incomplete) = fine_result.aggregate(&buckets);
+        if !incomplete.is_empty() {
+            tracing::info!(
+                "[{}] Skipping {} of {} candle(s) at resolution {} with missing {} candles",
+                symbol,
+                incomplete.len(),
+                buckets.len(),
+                coarse,
+                fine
+            );
+        }
+        let mut reported = coarse_result;
+        reported.0.retain(|time, _| aggregated.0.contains_key(time));
+        let mut report = ComparisonReport::compare(
+            symbol,
+            coarse,
+            from_utc,
+            to_utc,
+            &aggregated,
+            &reported,
+            tolerance,
+        );
+        report.left = format!("aggregated from {}", fine);
+        report.right = format!("reported at {}", coarse);
+        Ok(report)
+    }
+}
+
+/// Makes sure the path ends with a slash so `Url::join` appends to it instead of replacing
+/// the last segment.
+fn normalize_base_url(mut base_url: Url) -> Url {
+    if !base_url.path().ends_with('/') {
+        let path = format!("{}/", base_url.path());
+        base_url.set_path(&path);
+    }
+    base_url
+}
+
+/// A response with a non-2xx status. Error pages often say what went wrong, so the start of
+/// the body is kept.
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl HttpStatusError {
+    async fn from_response(response: reqwest::Response) -> Self {
+        HttpStatusError {
+            status: response.status(),
+            body: body_snippet(&response.text().await.unwrap_or_default()),
+        }
+    }
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Server returned {}", self.status)?;
+        if !self.body.is_empty() {
+            write!(f, ": {}", self.body)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+const BODY_SNIPPET_CHARS: usize = 200;
+
+/// The start of a response body on a single line, for error messages.
+fn body_snippet(body: &str) -> String {
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    match body.char_indices().nth(BODY_SNIPPET_CHARS) {
+        Some((end, _)) => format!("{}…", &body[..end]),
+        None => body,
+    }
+}
+
+/// `Retry-After` can either be a number of seconds or an HTTP date.
+fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_url(base_url: &str) -> String {
+        ApiClient::new(Url::parse(base_url).unwrap())
+            .history_url("SOL/USDC", Resolution::Minutes(60), 0, 3600)
+            .to_string()
+    }
+
+    #[test]
+    fn base_url_with_or_without_trailing_slash() {
+        let expected = "https://x.com/history?symbol=SOL%2FUSDC&resolution=60&from=0&to=3600";
+        assert_eq!(history_url("https://x.com/"), expected);
+        assert_eq!(history_url("https://x.com"), expected);
+        assert_eq!(
+            history_url("https://x.com/api/"),
+            "https://x.com/api/history?symbol=SOL%2FUSDC&resolution=60&from=0&to=3600"
+        );
+        assert_eq!(
+            history_url("https://x.com/api"),
+            history_url("https://x.com/api/")
+        );
+    }
+
+    #[test]
+    fn chunks_hold_at_most_max_bars_candles() {
+        let client = ApiClient::new(Url::parse("https://x.com/").unwrap()).with_max_bars(2);
+        let resolution = Resolution::Minutes(60);
+        let chunks = client.chunk_period(resolution, 0, 5 * 3600);
+        assert_eq!(chunks, vec![(0, 7199), (7200, 14399), (14400, 18000)]);
+        for (from, to) in chunks {
+            let from = DateTime::from_timestamp(from, 0).unwrap();
+            let to = DateTime::from_timestamp(to, 0).unwrap();
+            assert!(client.expected_candle_count(resolution, from, to) <= 2);
+        }
+    }
+
+    #[test]
+    fn daily_candles_aligned_before_midnight() {
+        let client = ApiClient::new(Url::parse("https://x.com").unwrap())
+            .with_alignment_offset(TimeDelta::minutes(-120));
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            client.expected_candle_times(
+                Resolution::Days(1),
+                at("2024-03-04T12:00:00Z"),
+                at("2024-03-07T00:00:00Z"),
+            ),
+            vec![
+                at("2024-03-04T22:00:00Z"),
+                at("2024-03-05T22:00:00Z"),
+                at("2024-03-06T22:00:00Z"),
+            ]
+        );
+        // Intraday resolutions that divide the offset keep their usual boundaries
+        assert_eq!(
+            client.expected_candle_count(
+                Resolution::Minutes(60),
+                at("2024-03-04T00:00:00Z"),
+                at("2024-03-04T03:00:00Z"),
+            ),
+            4
+        );
+    }
+
+    #[test]
+    fn a_panic_holding_a_shared_lock_doesnt_fail_other_checks() {
+        let client = ApiClient::new(Url::parse("https://x.com").unwrap());
+        let clone = client.clone();
+        std::thread::spawn(move || {
+            let _errors = clone.errors.lock().unwrap();
+            panic!("a symbol check panicked");
+        })
+        .join()
+        .unwrap_err();
+        assert!(client.errors.is_poisoned());
+
+        client.count_error(ErrorCategory::RateLimited);
+        assert_eq!(client.take_error_counts().total(), 1);
+    }
+}
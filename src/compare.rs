@@ -0,0 +1,196 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::display::{in_zone, DisplayInZone};
+use crate::history::{CandleData, StructuredApiResult, Time};
+use crate::resolution::Resolution;
+
+/// Differences between two sets of candles for the same symbol and period, usually what two
+/// endpoints returned. `left` and `right` describe the two sides, e.g. the endpoints' base URLs.
+#[derive(Debug, Clone, Serialize)]
+pub struct ComparisonReport {
+    pub symbol: String,
+    pub resolution: Resolution,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub left: String,
+    pub right: String,
+    pub only_left: Vec<DateTime<Utc>>,
+    pub only_right: Vec<DateTime<Utc>>,
+    pub mismatched: Vec<CandleMismatch>,
+}
+
+/// A candle both endpoints returned, but with different values.
+#[derive(Debug, Clone, Serialize)]
+pub struct CandleMismatch {
+    pub time: DateTime<Utc>,
+    pub left: CandleData,
+    pub right: CandleData,
+}
+
+/// How far apart two prices or volumes may be and still count as equal. Values match when
+/// they're within `absolute` of each other, or within `relative` times the larger magnitude.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Tolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            absolute: 1e-8,
+            relative: 0.0,
+        }
+    }
+}
+
+impl Tolerance {
+    /// Two missing (NaN) values match each other, but not a number.
+    pub fn matches(&self, a: f64, b: f64) -> bool {
+        if a.is_nan() || b.is_nan() {
+            return a.is_nan() && b.is_nan();
+        }
+        let difference = (a - b).abs();
+        difference <= self.absolute || difference <= self.relative * a.abs().max(b.abs())
+    }
+}
+
+impl CandleData {
+    /// Whether every price and the volume are equal within `tolerance`.
+    pub fn approx_eq(&self, other: &CandleData, tolerance: Tolerance) -> bool {
+        let pairs = [
+            (self.open, other.open),
+            (self.high, other.high),
+            (self.low, other.low),
+            (self.close, other.close),
+            (self.volume, other.volume),
+        ];
+        pairs.into_iter().all(|(a, b)| tolerance.matches(a, b))
+    }
+}
+
+impl ComparisonReport {
+    /// Compares two results candle by candle. `left`/`right` URLs are left empty for the caller
+    /// to fill in.
+    pub fn compare(
+        symbol: &str,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        left: &StructuredApiResult,
+        right: &StructuredApiResult,
+        tolerance: Tolerance,
+    ) -> Self {
+        let mut only_left: Vec<Time> = vec![];
+        let mut mismatched: Vec<CandleMismatch> = vec![];
+        for (time, left_candle) in left.sorted_candles() {
+            match right.0.get(&time) {
+                None => only_left.push(time),
+                Some(right_candle) if !left_candle.approx_eq(right_candle, tolerance) => mismatched
+                    .push(CandleMismatch {
+                        time: DateTime::from_timestamp(time, 0).unwrap(),
+                        left: left_candle.clone(),
+                        right: right_candle.clone(),
+                    }),
+                Some(_) => {}
+            }
+        }
+        let only_right: Vec<Time> = right
+            .sorted_candles()
+            .into_iter()
+            .map(|(time, _)| time)
+            .filter(|time| !left.0.contains_key(time))
+            .collect();
+
+        ComparisonReport {
+            symbol: symbol.to_string(),
+            resolution,
+            from,
+            to,
+            left: String::new(),
+            right: String::new(),
+            only_left: to_date_times(only_left),
+            only_right: to_date_times(only_right),
+            mismatched,
+        }
+    }
+
+    /// Number of candles the two endpoints disagree on.
+    pub fn differences(&self) -> usize {
+        self.only_left.len() + self.only_right.len() + self.mismatched.len()
+    }
+}
+
+fn to_date_times(times: Vec<Time>) -> Vec<DateTime<Utc>> {
+    times
+        .into_iter()
+        .map(|time| DateTime::from_timestamp(time, 0).unwrap())
+        .collect()
+}
+
+struct CandleValues<'a>(&'a CandleData);
+
+impl fmt::Display for CandleValues<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "o={} h={} l={} c={} v={}",
+            self.0.open, self.0.high, self.0.low, self.0.close, self.0.volume
+        )
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_in(f, Tz::UTC)
+    }
+}
+
+impl DisplayInZone for ComparisonReport {
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, tz: Tz) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {} -> {} (resolution {}): left = {}, right = {}: {} only on left, {} only on right, {} mismatched",
+            self.symbol,
+            in_zone(self.from, tz),
+            in_zone(self.to, tz),
+            self.resolution,
+            self.left,
+            self.right,
+            self.only_left.len(),
+            self.only_right.len(),
+            self.mismatched.len()
+        )?;
+        for time in &self.only_left {
+            write!(
+                f,
+                "\n[{}] < {} only on left",
+                self.symbol,
+                in_zone(*time, tz)
+            )?;
+        }
+        for time in &self.only_right {
+            write!(
+                f,
+                "\n[{}] > {} only on right",
+                self.symbol,
+                in_zone(*time, tz)
+            )?;
+        }
+        for mismatch in &self.mismatched {
+            write!(
+                f,
+                "\n[{}] ! {}: left {} / right {}",
+                self.symbol,
+                in_zone(mismatch.time, tz),
+                CandleValues(&mismatch.left),
+                CandleValues(&mismatch.right)
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+
+use url::Url;
+
+/// Settings that have to come from the environment, loaded once at startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub base_url: Url,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    MissingBaseUrl,
+    InvalidBaseUrl {
+        value: String,
+        error: url::ParseError,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingBaseUrl => write!(
+                f,
+                "BASE_URL is not set. Set it in your environment or .env file to the root of the \
+                 UDF datafeed, e.g. BASE_URL=https://example.com/udf"
+            ),
+            ConfigError::InvalidBaseUrl { value, error } => {
+                write!(f, "BASE_URL {:?} is not a valid URL: {}", value, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let value = match std::env::var("BASE_URL") {
+            Ok(value) if !value.trim().is_empty() => value.trim().to_string(),
+            _ => return Err(ConfigError::MissingBaseUrl),
+        };
+        let base_url =
+            Url::parse(&value).map_err(|error| ConfigError::InvalidBaseUrl { value, error })?;
+
+        Ok(Config { base_url })
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::report::GapReport;
+
+/// Expected and present candles per calendar day, over every symbol and resolution checked.
+/// Days are taken in the zone given to `new`, so they can follow `--display-tz`.
+#[derive(Debug, Clone)]
+pub struct GapCalendar {
+    tz: Tz,
+    days: BTreeMap<NaiveDate, DayCounts>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DayCounts {
+    pub expected: usize,
+    pub present: usize,
+}
+
+impl DayCounts {
+    pub fn missing(&self) -> usize {
+        self.expected - self.present
+    }
+
+    /// An empty day counts as fully available.
+    pub fn availability(&self) -> f64 {
+        if self.expected == 0 {
+            return 1.0;
+        }
+        self.present as f64 / self.expected as f64
+    }
+}
+
+impl GapCalendar {
+    pub fn new(tz: Tz) -> Self {
+        GapCalendar {
+            tz,
+            days: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the slots of `report`. `expected_times` should be the times it was checked against.
+    /// Failed periods are left out, and partial candles don't count as present.
+    pub fn add(
+        &mut self,
+        report: &GapReport,
+        expected_times: impl IntoIterator<Item = DateTime<Utc>>,
+    ) {
+        if report.is_failed() {
+            return;
+        }
+        let absent: HashSet<&DateTime<Utc>> =
+            report.missing.iter().chain(&report.partial).collect();
+        for time in expected_times {
+            let day = self
+                .days
+                .entry(time.with_timezone(&self.tz).date_naive())
+                .or_default();
+            day.expected += 1;
+            if !absent.contains(&time) {
+                day.present += 1;
+            }
+        }
+    }
+
+    /// Days in chronological order.
+    pub fn days(&self) -> impl Iterator<Item = (NaiveDate, DayCounts)> + '_ {
+        self.days.iter().map(|(date, counts)| (*date, *counts))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.days.is_empty()
+    }
+}
+
+#[derive(Serialize)]
+struct DayRow {
+    date: NaiveDate,
+    expected: usize,
+    present: usize,
+    missing: usize,
+    availability: f64,
+}
+
+impl Serialize for GapCalendar {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.days().map(|(date, counts)| DayRow {
+            date,
+            expected: counts.expected,
+            present: counts.present,
+            missing: counts.missing(),
+            availability: counts.availability(),
+        }))
+    }
+}
+
+impl fmt::Display for GapCalendar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<16} {:>9} {:>9} {:>9} {:>13}",
+            format!("day ({})", self.tz),
+            "expected",
+            "present",
+            "missing",
+            "availability"
+        )?;
+        for (date, counts) in self.days() {
+            write!(
+                f,
+                "\n{:<16} {:>9} {:>9} {:>9} {:>12.2}%",
+                // NaiveDate ignores the width otherwise
+                date.to_string(),
+                counts.expected,
+                counts.present,
+                counts.missing(),
+                counts.availability() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
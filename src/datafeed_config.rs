@@ -0,0 +1,53 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::resolution::{parse_advertised_resolutions, unadvertised_resolutions, Resolution};
+
+/// Capabilities a UDF datafeed reports on its `config` endpoint. Fields a datafeed leaves out
+/// are `None`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DatafeedConfig {
+    /// Resolutions in their wire form, e.g. `"1"`, `"60"` or `"1D"`
+    #[serde(default)]
+    pub supported_resolutions: Vec<String>,
+    pub supports_search: Option<bool>,
+    pub supports_group_request: Option<bool>,
+    pub supports_marks: Option<bool>,
+    pub supports_timescale_marks: Option<bool>,
+    pub supports_time: Option<bool>,
+}
+
+impl DatafeedConfig {
+    /// Those of `requested` the datafeed doesn't advertise. Empty when it doesn't list its
+    /// resolutions at all.
+    pub fn unsupported_resolutions(&self, requested: &[Resolution]) -> Vec<Resolution> {
+        unadvertised_resolutions(&self.supported_resolutions, requested)
+    }
+
+    /// Every advertised resolution the tool understands.
+    pub fn resolutions(&self) -> Vec<Resolution> {
+        parse_advertised_resolutions(&self.supported_resolutions)
+    }
+}
+
+impl fmt::Display for DatafeedConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |value: Option<bool>| match value {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown",
+        };
+        write!(
+            f,
+            "Datafeed config: resolutions {:?}, search {}, group requests {}, marks {}, \
+             timescale marks {}, server time {}",
+            self.supported_resolutions,
+            flag(self.supports_search),
+            flag(self.supports_group_request),
+            flag(self.supports_marks),
+            flag(self.supports_timescale_marks),
+            flag(self.supports_time)
+        )
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+use crate::history::Time;
+
+/// Human-readable output containing times that can be rendered in any time zone. Only the
+/// rendering changes, candle times are still computed in UTC. `Display` renders in UTC.
+pub trait DisplayInZone {
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, tz: Tz) -> fmt::Result;
+
+    fn in_zone(&self, tz: Tz) -> InZone<'_, Self> {
+        InZone { value: self, tz }
+    }
+}
+
+pub struct InZone<'a, T: ?Sized> {
+    value: &'a T,
+    tz: Tz,
+}
+
+impl<T: DisplayInZone + ?Sized> fmt::Display for InZone<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt_in(f, self.tz)
+    }
+}
+
+pub(crate) fn in_zone(time: DateTime<Utc>, tz: Tz) -> DateTime<Tz> {
+    time.with_timezone(&tz)
+}
+
+pub(crate) fn timestamp_in_zone(time: Time, tz: Tz) -> DateTime<Tz> {
+    in_zone(DateTime::from_timestamp(time, 0).unwrap(), tz)
+}
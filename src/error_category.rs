@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+
+use reqwest::StatusCode;
+use serde::Serialize;
+
+/// What kind of failure a request ran into, to tell a flaky provider from a broken network or
+/// a changed response schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Dns,
+    ConnectTimeout,
+    Connect,
+    Tls,
+    ReadTimeout,
+    /// HTTP 429
+    RateLimited,
+    /// HTTP 5xx
+    ServerError,
+    /// HTTP 4xx other than 429
+    ClientError,
+    /// A response that couldn't be parsed
+    Parse,
+    Other,
+}
+
+impl ErrorCategory {
+    /// `None` for successful statuses.
+    pub fn of_status(status: StatusCode) -> Option<Self> {
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            Some(ErrorCategory::RateLimited)
+        } else if status.is_server_error() {
+            Some(ErrorCategory::ServerError)
+        } else if status.is_client_error() {
+            Some(ErrorCategory::ClientError)
+        } else {
+            None
+        }
+    }
+
+    /// reqwest doesn't expose DNS and TLS failures directly, they're recognized by the messages
+    /// of the underlying errors.
+    pub fn of_reqwest(error: &reqwest::Error) -> Self {
+        let mut messages = vec![];
+        let mut source = error.source();
+        while let Some(error) = source {
+            messages.push(error.to_string().to_lowercase());
+            source = error.source();
+        }
+        let mentions = |words: &[&str]| {
+            messages
+                .iter()
+                .any(|message| words.iter().any(|word| message.contains(word)))
+        };
+
+        if error.is_timeout() && error.is_connect() {
+            ErrorCategory::ConnectTimeout
+        } else if error.is_timeout() {
+            ErrorCategory::ReadTimeout
+        } else if mentions(&["dns error", "failed to lookup address"]) {
+            ErrorCategory::Dns
+        } else if mentions(&["certificate", "tls", "ssl", "handshake"]) {
+            ErrorCategory::Tls
+        } else if error.is_connect() {
+            ErrorCategory::Connect
+        } else if error.is_decode() {
+            ErrorCategory::Parse
+        } else {
+            ErrorCategory::Other
+        }
+    }
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            ErrorCategory::Dns => "DNS",
+            ErrorCategory::ConnectTimeout => "connect timeout",
+            ErrorCategory::Connect => "connection",
+            ErrorCategory::Tls => "TLS",
+            ErrorCategory::ReadTimeout => "read timeout",
+            ErrorCategory::RateLimited => "429",
+            ErrorCategory::ServerError => "5xx",
+            ErrorCategory::ClientError => "4xx",
+            ErrorCategory::Parse => "parse",
+            ErrorCategory::Other => "other",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// Number of failed request attempts per category. Attempts that were retried count too, so
+/// errors show up even when the retries eventually succeeded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ErrorCounts(BTreeMap<ErrorCategory, usize>);
+
+impl ErrorCounts {
+    pub fn add(&mut self, category: ErrorCategory) {
+        *self.0.entry(category).or_default() += 1;
+    }
+
+    pub fn get(&self, category: ErrorCategory) -> usize {
+        self.0.get(&category).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for ErrorCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts: Vec<String> = self
+            .0
+            .iter()
+            .map(|(category, count)| format!("{}: {}", category, count))
+            .collect();
+        write!(
+            f,
+            "Failed request attempts by cause, retried ones included: {}",
+            counts.join(", ")
+        )
+    }
+}
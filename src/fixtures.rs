@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::cache::request_file_stem;
+use crate::history::ApiResult;
+use crate::resolution::Resolution;
+
+/// Raw `history` responses saved as pretty-printed JSON, one file per request, so a run can be
+/// captured once and replayed offline. Unlike the response cache, fixture names don't depend
+/// on the endpoint and never expire.
+#[derive(Debug, Clone)]
+pub enum Fixtures {
+    /// Save every response into the directory
+    Record(PathBuf),
+    /// Read every response from the directory instead of the network
+    Replay(PathBuf),
+}
+
+impl Fixtures {
+    pub fn dir(&self) -> &Path {
+        match self {
+            Fixtures::Record(dir) | Fixtures::Replay(dir) => dir,
+        }
+    }
+
+    pub fn path_for(&self, symbol: &str, resolution: Resolution, from: i64, to: i64) -> PathBuf {
+        self.dir().join(format!(
+            "{}.json",
+            request_file_stem(symbol, resolution, from, to)
+        ))
+    }
+
+    /// Fails if no fixture was recorded for the request.
+    pub fn load(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+    ) -> anyhow::Result<ApiResult> {
+        let path = self.path_for(symbol, resolution, from, to);
+        let bytes = std::fs::read(&path)
+            .with_context(|| format!("No fixture recorded at {}", path.display()))?;
+        serde_json::from_slice(&bytes)
+            .with_context(|| format!("Malformed fixture {}", path.display()))
+    }
+
+    pub fn save(
+        &self,
+        symbol: &str,
+        resolution: Resolution,
+        from: i64,
+        to: i64,
+        result: &ApiResult,
+    ) -> anyhow::Result<()> {
+        let path = self.path_for(symbol, resolution, from, to);
+        std::fs::create_dir_all(self.dir())?;
+        let mut json = serde_json::to_string_pretty(result)?;
+        json.push('\n');
+        std::fs::write(&path, json)
+            .with_context(|| format!("Failed to write fixture {}", path.display()))
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+
+use chrono::{DateTime, NaiveDate, Timelike, Utc};
+
+use crate::report::GapReport;
+use crate::resolution::Resolution;
+
+const CELL_HEIGHT: u32 = 12;
+const LABEL_WIDTH: u32 = 90;
+const TITLE_HEIGHT: u32 = 24;
+/// Grids are squeezed into roughly this width, with cells at least one pixel wide.
+const GRID_WIDTH: u32 = 1440;
+
+const PRESENT_COLOR: &str = "#2da44e";
+const MISSING_COLOR: &str = "#cf222e";
+const UNCHECKED_COLOR: &str = "#eaeef2";
+
+/// Candle slots laid out as one grid per symbol and resolution: a row per UTC day and a column
+/// per slot within the day. Present slots are green, missing ones red and slots no period
+/// covered grey.
+#[derive(Debug, Clone, Default)]
+pub struct Heatmap {
+    grids: Vec<Grid>,
+}
+
+#[derive(Debug, Clone)]
+struct Grid {
+    symbol: String,
+    resolution: Resolution,
+    /// Whether each checked slot was present. Slots checked by several periods count as present
+    /// if any period found them.
+    slots: BTreeMap<DateTime<Utc>, bool>,
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Heatmap::default()
+    }
+
+    /// Adds the slots of `report`. `expected_times` should be the times it was checked against.
+    /// Failed periods are left out, their slots stay grey.
+    pub fn add(
+        &mut self,
+        report: &GapReport,
+        expected_times: impl IntoIterator<Item = DateTime<Utc>>,
+    ) {
+        if report.is_failed() {
+            return;
+        }
+        let grid =
+            match self.grids.iter_mut().position(|grid| {
+                grid.symbol == report.symbol && grid.resolution == report.resolution
+            }) {
+                Some(index) => &mut self.grids[index],
+                None => {
+                    self.grids.push(Grid {
+                        symbol: report.symbol.clone(),
+                        resolution: report.resolution,
+                        slots: BTreeMap::new(),
+                    });
+                    self.grids.last_mut().unwrap()
+                }
+            };
+        let missing: HashSet<&DateTime<Utc>> = report.missing.iter().collect();
+        for time in expected_times {
+            let present = !missing.contains(&time);
+            *grid.slots.entry(time).or_insert(present) |= present;
+        }
+    }
+
+    pub fn write_svg(&self, mut writer: impl Write) -> anyhow::Result<()> {
+        let layouts: Vec<GridLayout> = self.grids.iter().map(GridLayout::new).collect();
+        let width = LABEL_WIDTH
+            + layouts
+                .iter()
+                .map(|layout| layout.columns * layout.cell_width)
+                .max()
+                .unwrap_or(0);
+        let height: u32 = layouts.iter().map(GridLayout::height).sum();
+
+        writeln!(
+            writer,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="monospace" font-size="10">"#
+        )?;
+        let mut top = 0;
+        for (grid, layout) in self.grids.iter().zip(&layouts) {
+            writeln!(
+                writer,
+                r#"<text x="0" y="{}" font-size="12">{} (resolution {}, UTC)</text>"#,
+                top + TITLE_HEIGHT - 8,
+                escape(&grid.symbol),
+                grid.resolution
+            )?;
+            top += TITLE_HEIGHT;
+            for (row, day) in layout.days.iter().enumerate() {
+                let y = top + row as u32 * CELL_HEIGHT;
+                writeln!(
+                    writer,
+                    r#"<text x="0" y="{}">{}</text>"#,
+                    y + CELL_HEIGHT - 2,
+                    day
+                )?;
+                writeln!(
+                    writer,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+                    LABEL_WIDTH,
+                    y,
+                    layout.columns * layout.cell_width,
+                    CELL_HEIGHT - 1,
+                    UNCHECKED_COLOR
+                )?;
+            }
+            for (time, present) in &grid.slots {
+                let row = layout.days.binary_search(&time.date_naive()).unwrap() as u32;
+                let column = time.num_seconds_from_midnight() / layout.slot_seconds;
+                writeln!(
+                    writer,
+                    r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"><title>{}</title></rect>"#,
+                    LABEL_WIDTH + column * layout.cell_width,
+                    top + row * CELL_HEIGHT,
+                    layout.cell_width,
+                    CELL_HEIGHT - 1,
+                    if *present {
+                        PRESENT_COLOR
+                    } else {
+                        MISSING_COLOR
+                    },
+                    time
+                )?;
+            }
+            top += layout.days.len() as u32 * CELL_HEIGHT;
+        }
+        writeln!(writer, "</svg>")?;
+        Ok(())
+    }
+
+    /// Prints a character per slot, a day per row, wrapping rows longer than `width`. Present
+    /// slots are `█`, missing ones `X` and unchecked ones `·`, colored green, red and grey when
+    /// `color` is set.
+    pub fn write_text(
+        &self,
+        mut writer: impl Write,
+        width: usize,
+        color: bool,
+    ) -> anyhow::Result<()> {
+        // `YYYY-MM-DD `
+        const LABEL: usize = 11;
+        let per_line = width.saturating_sub(LABEL).max(1);
+        let paint = |symbol: char, ansi: &str| {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", ansi, symbol)
+            } else {
+                symbol.to_string()
+            }
+        };
+
+        for grid in &self.grids {
+            let layout = GridLayout::new(grid);
+            writeln!(
+                writer,
+                "{} (resolution {}, UTC)",
+                grid.symbol, grid.resolution
+            )?;
+            for day in &layout.days {
+                let mut row = vec![paint('·', "90"); layout.columns as usize];
+                for (time, present) in grid
+                    .slots
+                    .range(day.and_hms_opt(0, 0, 0).unwrap().and_utc()..)
+                    .take_while(|(time, _)| time.date_naive() == *day)
+                {
+                    let column = time.num_seconds_from_midnight() / layout.slot_seconds;
+                    row[column as usize] = if *present {
+                        paint('█', "32")
+                    } else {
+                        paint('X', "31")
+                    };
+                }
+                for (index, line) in row.chunks(per_line).enumerate() {
+                    let label = if index == 0 {
+                        day.to_string()
+                    } else {
+                        String::new()
+                    };
+                    writeln!(writer, "{:<LABEL$}{}", label, line.concat())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct GridLayout {
+    days: Vec<NaiveDate>,
+    /// Seconds per column. Candles of a day or longer get a single column.
+    slot_seconds: u32,
+    columns: u32,
+    cell_width: u32,
+}
+
+impl GridLayout {
+    fn new(grid: &Grid) -> Self {
+        let mut days: Vec<NaiveDate> = grid.slots.keys().map(|time| time.date_naive()).collect();
+        days.dedup();
+        let slot_seconds = (grid.resolution.approximate_duration().num_seconds() as u32).min(86400);
+        let columns = 86400_u32.div_ceil(slot_seconds);
+        GridLayout {
+            days,
+            slot_seconds,
+            columns,
+            cell_width: (GRID_WIDTH / columns).clamp(1, 40),
+        }
+    }
+
+    fn height(&self) -> u32 {
+        TITLE_HEIGHT + self.days.len() as u32 * CELL_HEIGHT
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
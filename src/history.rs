@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+pub const STATUS_OK: &str = "ok";
+pub const STATUS_NO_DATA: &str = "no_data";
+pub const STATUS_ERROR: &str = "error";
+
+/// Raw response of the UDF `history` endpoint. Candle fields are stored column-wise.
+///
+/// The candle arrays are only guaranteed to be present when `s` is `"ok"`. A `"no_data"`
+/// response may carry `nextTime`, an `"error"` response carries `errmsg`.
+///
+/// Some feeds send `null` prices for a candle that's still forming. They're read as NaN, which
+/// makes the candle partial, see `CandleData::is_partial`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiResult {
+    pub s: String,
+    #[serde(default)]
+    pub time: Vec<i64>,
+    #[serde(default, deserialize_with = "nullable_prices")]
+    pub close: Vec<f64>,
+    #[serde(default, deserialize_with = "nullable_prices")]
+    pub open: Vec<f64>,
+    #[serde(default, deserialize_with = "nullable_prices")]
+    pub high: Vec<f64>,
+    #[serde(default, deserialize_with = "nullable_prices")]
+    pub low: Vec<f64>,
+    /// Fractional volumes are common, e.g. base-asset volume on crypto feeds. Integer JSON values
+    /// deserialize into this just as well. Some feeds leave the column out for instruments
+    /// without volume, the candles then get a volume of zero.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<Vec<f64>>,
+    #[serde(rename = "nextTime", skip_serializing_if = "Option::is_none")]
+    pub next_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errmsg: Option<String>,
+}
+
+pub type Time = i64;
+
+/// Prices with `null` entries as NaN. NaN serializes back to `null`, so cached and recorded
+/// responses read the same way.
+fn nullable_prices<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<f64>, D::Error> {
+    let prices: Vec<Option<f64>> = Deserialize::deserialize(deserializer)?;
+    Ok(prices
+        .into_iter()
+        .map(|price| price.unwrap_or(f64::NAN))
+        .collect())
+}
+
+/// Candles keyed by their unix timestamp. Iterating the map directly visits them in no
+/// particular order, use `sorted_candles` for anything that ends up in output.
+#[derive(Debug, Clone)]
+pub struct StructuredApiResult(pub HashMap<Time, CandleData>);
+
+impl StructuredApiResult {
+    /// Candles in ascending time order.
+    pub fn sorted_candles(&self) -> Vec<(Time, &CandleData)> {
+        let mut candles: Vec<(Time, &CandleData)> = self
+            .0
+            .iter()
+            .map(|(time, candle)| (*time, candle))
+            .collect();
+        candles.sort_unstable_by_key(|(time, _)| *time);
+        candles
+    }
+
+    /// Times of the first and last candle, `None` if there are no candles.
+    pub fn observed_range(&self) -> Option<(Time, Time)> {
+        let first = self.0.keys().min()?;
+        let last = self.0.keys().max()?;
+        Some((*first, *last))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CandleData {
+    pub close: f64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub volume: f64,
+}
+
+impl TryFrom<ApiResult> for StructuredApiResult {
+    type Error = anyhow::Error;
+
+    /// Fails if any candle column has a different length than `time`, naming the offending field.
+    fn try_from(value: ApiResult) -> Result<Self, Self::Error> {
+        let columns = [
+            ("close", Some(value.close.len())),
+            ("open", Some(value.open.len())),
+            ("high", Some(value.high.len())),
+            ("low", Some(value.low.len())),
+            ("volume", value.volume.as_ref().map(Vec::len)),
+        ];
+        for (field, len) in columns
+            .into_iter()
+            .filter_map(|(field, len)| Some((field, len?)))
+        {
+            if len != value.time.len() {
+                anyhow::bail!(
+                    "Malformed response: `{}` has {} entries but `time` has {}",
+                    field,
+                    len,
+                    value.time.len()
+                );
+            }
+        }
+
+        let mut hmap = HashMap::with_capacity(value.time.len());
+        for (time, candle) in value.candles() {
+            hmap.insert(time, candle);
+        }
+        Ok(StructuredApiResult(hmap))
+    }
+}
+
+impl ApiResult {
+    /// Converts an `ok` response into candles. `no_data` yields an empty result and `error`
+    /// fails with the server's message.
+    pub fn into_structured(self) -> anyhow::Result<StructuredApiResult> {
+        match self.s.as_str() {
+            STATUS_OK => self.try_into(),
+            STATUS_NO_DATA => {
+                tracing::debug!("API reported no_data. nextTime = {:?}", self.next_time);
+                Ok(StructuredApiResult(HashMap::new()))
+            }
+            STATUS_ERROR => anyhow::bail!(
+                "API returned an error: {}",
+                self.errmsg.as_deref().unwrap_or("<no errmsg>")
+            ),
+            status => anyhow::bail!("API returned unknown status {:?}", status),
+        }
+    }
+
+    /// Whether the response has a `volume` column.
+    pub fn has_volume(&self) -> bool {
+        self.volume.is_some()
+    }
+
+    /// Candles in response order. Stops at the shortest column, so it never panics on
+    /// malformed responses.
+    pub fn candles(&self) -> impl Iterator<Item = (Time, CandleData)> + '_ {
+        let no_volume = if self.has_volume() {
+            0
+        } else {
+            self.time.len()
+        };
+        let volumes = self
+            .volume
+            .iter()
+            .flatten()
+            .chain(std::iter::repeat_n(&0.0, no_volume));
+        self.time
+            .iter()
+            .zip(&self.close)
+            .zip(&self.open)
+            .zip(&self.high)
+            .zip(&self.low)
+            .zip(volumes)
+            .map(|(((((time, close), open), high), low), volume)| {
+                (
+                    *time,
+                    CandleData {
+                        close: *close,
+                        open: *open,
+                        high: *high,
+                        low: *low,
+                        volume: *volume,
+                    },
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(json: &str) -> ApiResult {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn ragged_columns_are_an_error_naming_the_field() {
+        let ragged = result(
+            r#"{"s":"ok","time":[60,120,180],"open":[1,1,1],"high":[2,2,2],"low":[0,0,0],
+                "close":[1,1,1],"volume":[5,5]}"#,
+        );
+        assert_eq!(ragged.candles().count(), 2);
+        let error = StructuredApiResult::try_from(ragged).unwrap_err();
+        assert!(
+            error.to_string().contains("`volume` has 2 entries"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn decimal_and_integer_volumes_deserialize() {
+        let result = result(
+            r#"{"s":"ok","time":[60,120],"open":[1,1],"high":[2,2],"low":[0,0],"close":[1,1],
+                "volume":[12.3456,7]}"#,
+        );
+        let structured = StructuredApiResult::try_from(result).unwrap();
+        assert_eq!(structured.0[&60].volume, 12.3456);
+        assert_eq!(structured.0[&120].volume, 7.0);
+    }
+
+    #[test]
+    fn null_prices_make_a_candle_partial() {
+        let result = result(
+            r#"{"s":"ok","time":[60,120],"open":[1,1],"high":[2,null],"low":[0,0],
+                "close":[1,null],"volume":[5,5]}"#,
+        );
+        let structured = StructuredApiResult::try_from(result).unwrap();
+        assert!(!structured.0[&60].is_partial());
+        assert!(structured.0[&120].close.is_nan());
+        assert!(structured.0[&120].is_partial());
+    }
+
+    #[test]
+    fn a_missing_volume_column_gives_zero_volumes() {
+        let result = result(
+            r#"{"s":"ok","time":[60,120],"open":[1,1],"high":[2,2],"low":[0,0],"close":[1,1]}"#,
+        );
+        assert!(!result.has_volume());
+        let structured = StructuredApiResult::try_from(result).unwrap();
+        assert_eq!(structured.0.len(), 2);
+        assert_eq!(structured.0[&60].volume, 0.0);
+    }
+}
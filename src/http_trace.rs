@@ -0,0 +1,81 @@
+use reqwest::header::{HeaderMap, SET_COOKIE};
+use reqwest::Response;
+use url::Url;
+
+/// Logs every request and response as sent and received, at debug level under the
+/// `tv_debug::http_trace` target. Lines are emitted inside the span of the request, so they
+/// carry its correlation id.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HttpTrace {
+    /// Response bodies longer than this many characters are cut short
+    pub body_limit: Option<usize>,
+}
+
+impl HttpTrace {
+    pub fn request(&self, url: &Url, headers: &HeaderMap) {
+        let mut url = url.clone();
+        if url.password().is_some() {
+            let _ = url.set_password(Some("redacted"));
+        }
+        tracing::debug!("> GET {}{}", url, format_headers(headers, ">"));
+    }
+
+    pub fn response(&self, response: &Response) {
+        tracing::debug!(
+            "< {}{}",
+            response.status(),
+            format_headers(response.headers(), "<")
+        );
+    }
+
+    pub fn body(&self, body: &str) {
+        match self
+            .body_limit
+            .and_then(|limit| body.char_indices().nth(limit))
+        {
+            Some((end, _)) => {
+                tracing::debug!("< {}… ({} more bytes)", &body[..end], body.len() - end)
+            }
+            None => tracing::debug!("< {}", body),
+        }
+    }
+}
+
+/// One `prefix name: value` line per header. Sensitive values, such as the API key, and cookies
+/// are redacted.
+fn format_headers(headers: &HeaderMap, prefix: &str) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if value.is_sensitive() || name == SET_COOKIE {
+                "<redacted>"
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            format!("\n{} {}: {}", prefix, name, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn sensitive_header_values_are_redacted() {
+        let mut api_key = HeaderValue::from_static("Bearer abc123");
+        api_key.set_sensitive(true);
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", api_key);
+        headers.insert("accept", HeaderValue::from_static("application/json"));
+        headers.insert(SET_COOKIE, HeaderValue::from_static("session=s3cret"));
+
+        let formatted = format_headers(&headers, ">");
+        assert!(formatted.contains("\n> authorization: <redacted>"));
+        assert!(formatted.contains("\n> set-cookie: <redacted>"));
+        assert!(formatted.contains("\n> accept: application/json"));
+        assert!(!formatted.contains("abc123"));
+        assert!(!formatted.contains("s3cret"));
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Wall-clock latency of the `history` requests sent over the network, from sending the
+/// successful attempt to reading its body. Durations are serialized as fractional seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencyStats {
+    pub requests: usize,
+    #[serde(serialize_with = "as_secs")]
+    pub min: Duration,
+    #[serde(serialize_with = "as_secs")]
+    pub median: Duration,
+    #[serde(serialize_with = "as_secs")]
+    pub p95: Duration,
+    #[serde(serialize_with = "as_secs")]
+    pub p99: Duration,
+    #[serde(serialize_with = "as_secs")]
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Nearest-rank percentiles of `samples`, `None` if there are none.
+    pub fn from_samples(mut samples: Vec<Duration>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let percentile = |p: f64| {
+            let rank = (p * samples.len() as f64).ceil() as usize;
+            samples[rank.clamp(1, samples.len()) - 1]
+        };
+        Some(LatencyStats {
+            requests: samples.len(),
+            min: samples[0],
+            median: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: samples[samples.len() - 1],
+        })
+    }
+}
+
+fn as_secs<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Request latency over {} request(s): min {:?}, median {:?}, p95 {:?}, p99 {:?}, max {:?}",
+            self.requests, self.min, self.median, self.p95, self.p99, self.max
+        )
+    }
+}
@@ -0,0 +1,73 @@
+mod adapter;
+mod aggregate;
+mod alert;
+mod archive;
+mod bench;
+mod cache;
+mod calendar;
+mod client;
+mod compare;
+mod config;
+mod daily;
+mod datafeed_config;
+mod display;
+mod error_category;
+mod fixtures;
+mod heatmap;
+mod history;
+mod http_trace;
+mod latency;
+mod metrics;
+mod output;
+mod periods;
+mod rate_limit;
+mod report;
+mod resolution;
+mod retry;
+mod summary;
+mod symbol_info;
+mod time_expression;
+mod validation;
+mod volume;
+
+pub use adapter::{ResponseAdapter, ResponseFormat, RowWiseAdapter, SchemaCheck, UdfAdapter};
+pub use aggregate::aggregate_candles;
+pub use alert::{GapAlert, Webhook, WebhookFormat};
+pub use archive::{ArchivedGaps, ArchivedRun, GapDiff, ReportArchive};
+pub use bench::{run_bench, BenchReport};
+pub use cache::ResponseCache;
+pub use calendar::{Session, TradingCalendar};
+pub use client::{ApiClient, HttpStatusError, HttpVersion};
+pub use compare::{CandleMismatch, ComparisonReport, Tolerance};
+pub use config::{Config, ConfigError};
+pub use daily::{DayCounts, GapCalendar};
+pub use datafeed_config::DatafeedConfig;
+pub use display::{DisplayInZone, InZone};
+pub use error_category::{ErrorCategory, ErrorCounts};
+pub use fixtures::Fixtures;
+pub use heatmap::Heatmap;
+pub use history::{ApiResult, CandleData, StructuredApiResult, Time};
+pub use http_trace::HttpTrace;
+pub use latency::LatencyStats;
+pub use metrics::Metrics;
+pub use output::{
+    write_csv, write_json, write_ndjson_slots, write_ndjson_summary, JsonOutput, JsonReport,
+    NdjsonSlot, OutputFormat,
+};
+pub use periods::{
+    generate_coverage_periods, generate_random_time_periods, generate_sampled_periods,
+    period_distribution, read_periods_file, window_coverage, Mode, Sampling,
+};
+pub use rate_limit::{RateLimiter, RequestBudget, RequestBudgetExhausted};
+pub use report::{group_gaps, GapRange, GapReport, PeriodStatus};
+pub use resolution::{
+    candle_times, expected_candle_times, first_normalized_time_at_or_after,
+    last_normalized_time_at_or_before, next_normalized_time_for_resolution,
+    parse_advertised_resolutions, unadvertised_resolutions, Resolution,
+};
+pub use retry::RetryPolicy;
+pub use summary::{GapHistogram, LongestGap, Summary, SymbolStatus, SymbolSummary};
+pub use symbol_info::SymbolInfo;
+pub use time_expression::{parse_duration, TimeExpression};
+pub use validation::{AnomalyKind, DuplicateCandle, FlatRun};
+pub use volume::{VolumeOutlier, VolumeStats};
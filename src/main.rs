@@ -1,271 +1,1451 @@
 #![allow(dead_code)]
 
-use std::collections::HashMap;
+mod cli;
 
-use chrono::{DateTime, Datelike, Days, NaiveDate, TimeDelta, Timelike, Utc};
-use rand::Rng;
-use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Days, TimeDelta, Utc};
+use cli::{Args, HttpVersionArg, LogFormat, ModeArg, SamplingArg};
+use futures::{stream, FutureExt, StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{NoProxy, Proxy};
+use tokio::time::MissedTickBehavior;
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+use tv_debug::{
+    first_normalized_time_at_or_after, generate_coverage_periods, generate_sampled_periods,
+    group_gaps, last_normalized_time_at_or_before, period_distribution, read_periods_file,
+    run_bench, write_csv, write_json, write_ndjson_slots, write_ndjson_summary, ApiClient,
+    ComparisonReport, Config, DisplayInZone, ErrorCounts, Fixtures, GapAlert, GapCalendar,
+    GapReport, Heatmap, HttpTrace, HttpVersion, Metrics, Mode, OutputFormat, ReportArchive,
+    RequestBudget, RequestBudgetExhausted, Resolution, ResponseCache, RetryPolicy, Sampling,
+    Summary, TimeExpression, Tolerance, TradingCalendar, Webhook,
+};
+use url::Url;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenv::dotenv()?;
-    env_logger::init();
-
-    let upper_bound = Utc::now();
-    let lower_bound = upper_bound.checked_sub_days(Days::new(14)).unwrap(); // From two weeks ago
-    let resolution = 60;
-
-    log::info!(
-        "Running API tests for data availability between {} and {}. Resolution = {}",
-        lower_bound,
-        upper_bound,
-        resolution
+    // The variables can just as well come from the environment, but a broken .env is an error
+    match dotenv::dotenv() {
+        Err(dotenv::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+        result => {
+            result?;
+        }
+    }
+    let mut args = Args::load()?;
+    init_tracing(args.log_format, args.log_level(), args.trace_http);
+
+    let started = Utc::now();
+    let mut windows = Windows::resolve(&args, started)?;
+    windows.log_overrides(&args);
+    anyhow::ensure!(
+        args.requests_per_second > 0.0,
+        "Requests per second must be positive"
     );
-    test_time_period_for_api(lower_bound, upper_bound, resolution, Mode::Simple).await?;
+    anyhow::ensure!(
+        !args.since_last_candle || args.mode == ModeArg::Simple,
+        "--since-last-candle needs the simple mode"
+    );
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let retry_policy = RetryPolicy {
+        max_retries: args.retry_attempts,
+        base_delay: Duration::from_millis(args.retry_base_delay_ms),
+    };
+    let cache = match &args.cache_dir {
+        // Cached responses would make a benchmark meaningless
+        Some(dir) if !args.no_cache && args.bench.is_none() => Some(
+            ResponseCache::new(dir, Duration::from_secs(args.cache_ttl_secs))
+                .with_refresh(args.refresh_cache),
+        ),
+        _ => None,
+    };
+    let calendar = args
+        .calendar
+        .as_deref()
+        .map(TradingCalendar::from_file)
+        .transpose()?;
+    let fixtures = match (&args.record, &args.replay) {
+        (Some(dir), _) => Some(Fixtures::Record(dir.clone())),
+        (_, Some(dir)) => Some(Fixtures::Replay(dir.clone())),
+        (None, None) => None,
+    };
+    let http_client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(args.connect_timeout_secs))
+        .timeout(Duration::from_secs(args.request_timeout_secs))
+        .pool_max_idle_per_host(args.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(args.pool_idle_timeout_secs));
+    let http_client = match args.http_version {
+        HttpVersionArg::Auto => HttpVersion::Auto,
+        HttpVersionArg::Http1 => HttpVersion::Http1,
+        HttpVersionArg::Http2 => HttpVersion::Http2,
+    }
+    .configure(http_client);
+    let http_client = match args.keep_alive_secs {
+        Some(secs) => http_client
+            .tcp_keepalive(Duration::from_secs(secs))
+            .http2_keep_alive_interval(Duration::from_secs(secs))
+            .http2_keep_alive_while_idle(true),
+        None => http_client,
+    };
+    // Without an explicit proxy, reqwest picks one up from the environment by itself
+    let http_client = match &args.proxy {
+        Some(proxy) => {
+            tracing::info!(
+                "Sending requests through the proxy at {}",
+                proxy.host_str().unwrap_or_default()
+            );
+            http_client.proxy(Proxy::all(proxy.clone())?.no_proxy(NoProxy::from_env()))
+        }
+        None => http_client,
+    };
+    let http_client = match &args.ca_cert {
+        Some(path) => {
+            let bundle = std::fs::read(path).with_context(|| {
+                format!("Failed to read CA certificates from {}", path.display())
+            })?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&bundle)
+                .with_context(|| format!("Invalid PEM certificates in {}", path.display()))?;
+            tracing::debug!(
+                "Trusting {} extra CA certificate(s) from {}",
+                certificates.len(),
+                path.display()
+            );
+            certificates
+                .into_iter()
+                .fold(http_client, |builder, certificate| {
+                    builder.add_root_certificate(certificate)
+                })
+        }
+        None => http_client,
+    };
+    if args.insecure {
+        tracing::warn!(
+            "!!! TLS certificate validation is DISABLED. Responses can be intercepted or forged, \
+             only use --insecure against test endpoints !!!"
+        );
+    }
+    let http_client = http_client
+        .danger_accept_invalid_certs(args.insecure)
+        .build()?;
+    let metrics = match args.metrics_addr {
+        Some(addr) => {
+            let metrics = Metrics::new();
+            metrics.serve(addr).await?;
+            Some(metrics)
+        }
+        None => None,
+    };
+    let mut headers = HeaderMap::new();
+    for (name, value) in &args.headers {
+        headers.append(name.clone(), value.clone());
+    }
+    if let Some(api_key) = &args.api_key {
+        let value = HeaderValue::from_str(api_key)
+            .map_err(|_| anyhow::anyhow!("The API key isn't a valid header value"))?;
+        headers.insert(args.auth_header.clone(), value);
+    }
+    // One budget for every endpoint
+    let request_budget = args.max_requests.map(RequestBudget::new);
+    let build_client = |base_url: Url| {
+        let client = ApiClient::new(base_url)
+            .with_http_client(http_client.clone())
+            .with_headers(headers.clone())
+            // Each endpoint gets its own rate limiter
+            .with_rate_limit(args.requests_per_second)
+            .with_week_start(args.week_start)
+            .with_alignment_offset(TimeDelta::minutes(args.alignment_offset))
+            .with_retry_policy(retry_policy)
+            .with_ohlc_validation(args.validate_ohlc)
+            .with_max_bars(args.max_bars)
+            .with_next_time_following(args.follow_next_time)
+            .with_countback(args.countback)
+            .with_current_candle_excluded(
+                args.exclude_current_candle.unwrap_or(args.watch.is_some()),
+            )
+            .with_response_adapter(args.response_format.adapter(args.schema_check));
+        let client = match args.flat_candles {
+            Some(min_candles) => client.with_flat_candle_detection(min_candles),
+            None => client,
+        };
+        let client = if args.volume_stats {
+            client.with_volume_stats(args.volume_z_threshold)
+        } else {
+            client
+        };
+        let client = match &calendar {
+            Some(calendar) => client.with_calendar(calendar.clone()),
+            None => client,
+        };
+        let client = match &cache {
+            Some(cache) => client.with_cache(cache.clone()),
+            None => client,
+        };
+        let client = match &metrics {
+            Some(metrics) => client.with_metrics(metrics.clone()),
+            None => client,
+        };
+        let client = match &request_budget {
+            Some(budget) => client.with_request_budget(budget.clone()),
+            None => client,
+        };
+        let client = if args.trace_http {
+            client.with_http_trace(HttpTrace {
+                body_limit: args.trace_http_body_limit,
+            })
+        } else {
+            client
+        };
+        match &fixtures {
+            Some(fixtures) => client.with_fixtures(fixtures.clone()),
+            None => client,
+        }
+    };
+    let fallbacks = args
+        .fallback_urls
+        .iter()
+        .cloned()
+        .map(build_client)
+        .collect();
+    let client = build_client(config.base_url).with_fallbacks(fallbacks);
+    let right = args.compare_with.clone().map(build_client);
+
+    if args.probe_config || args.all_resolutions {
+        match client.fetch_datafeed_config().await {
+            Ok(datafeed_config) => {
+                tracing::info!("{}", datafeed_config);
+                if args.all_resolutions {
+                    args.resolutions = datafeed_config.resolutions();
+                    anyhow::ensure!(
+                        !args.resolutions.is_empty(),
+                        "The datafeed doesn't advertise any resolutions"
+                    );
+                }
+                for resolution in datafeed_config.unsupported_resolutions(&args.resolutions) {
+                    tracing::warn!(
+                        "Resolution {} isn't among the datafeed's supported resolutions {:?}",
+                        resolution,
+                        datafeed_config.supported_resolutions
+                    );
+                }
+            }
+            Err(e) if args.all_resolutions => {
+                return Err(e.context("Failed to fetch the datafeed config"))
+            }
+            Err(e) => tracing::warn!("Couldn't fetch the datafeed config: {:#}", e),
+        }
+    }
+
+    let mode = match args.mode {
+        ModeArg::Simple => Mode::Simple,
+        ModeArg::Randomized => {
+            let seed = args.seed.unwrap_or_else(rand::random);
+            tracing::info!("Generating random periods with seed {}", seed);
+            Mode::Randomized {
+                limit: args.limit,
+                seed,
+                // Long enough for the coarsest resolution, so no period is empty for any of them
+                min_span: args
+                    .resolutions
+                    .iter()
+                    .map(|resolution| resolution.approximate_duration())
+                    .max()
+                    .unwrap()
+                    // Within i32, clap makes sure of that
+                    .checked_mul(args.min_span_candles as i32)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "--min-span-candles {} is too long a span",
+                            args.min_span_candles
+                        )
+                    })?,
+                sampling: match args.sampling {
+                    SamplingArg::Uniform => Sampling::Uniform,
+                    SamplingArg::Recent => Sampling::Recent {
+                        within: args.recent_window,
+                        periods: args.recent_periods,
+                    },
+                    SamplingArg::Stratified => Sampling::Stratified,
+                },
+            }
+        }
+        ModeArg::Coverage => {
+            let seed = args.seed.unwrap_or_else(rand::random);
+            tracing::info!("Shuffling coverage periods with seed {}", seed);
+            Mode::Coverage {
+                segments: args.limit,
+                seed,
+            }
+        }
+        // clap makes sure the file is given in this mode
+        ModeArg::FromFile => Mode::FromFile {
+            periods: read_periods_file(args.periods_file.as_deref().unwrap())?,
+        },
+    };
+
+    let webhook = args
+        .webhook_url
+        .clone()
+        .map(|url| Webhook::new(url, args.webhook_format).with_http_client(http_client.clone()));
+
+    // Replayed runs stay offline, and dry runs don't send anything
+    if !args.skip_symbol_info && !args.dry_run && args.replay.is_none() {
+        check_symbol_info(&client, &args).await;
+    }
+    if args.detect_listing && !args.dry_run && args.replay.is_none() {
+        windows.trim_to_listings(&client, &args).await;
+    }
+    if args.since_last_candle && !args.dry_run {
+        windows.start_at_last_candles(&client, &args).await;
+    }
+
+    if let Some(requests) = args.bench {
+        let (_, upper_bound) = windows.for_symbol(&args.symbols[0]);
+        return bench(&client, &args, upper_bound, requests).await;
+    }
+
+    if let Some(right) = right {
+        return compare_endpoints(&client, &right, &args, &windows, &mode).await;
+    }
+
+    if let Some(fine) = args.aggregate_from {
+        return cross_check_resolutions(&client, fine, &args, &windows, &mode).await;
+    }
+
+    let archive = args.report_dir.as_ref().map(|dir| {
+        let archive = ReportArchive::new(dir);
+        match args.report_retention {
+            Some(retention) => archive.with_retention(retention),
+            None => archive,
+        }
+    });
+
+    if let Some(interval) = args.watch {
+        return watch(
+            &client,
+            webhook.as_ref(),
+            metrics.as_ref(),
+            archive.as_ref(),
+            &args,
+            &mode,
+            interval,
+        )
+        .await;
+    }
+
+    let (lower_bound, upper_bound) = windows.default;
+    tracing::info!(
+        "Running API tests for data availability between {} and {}. Resolutions = {:?}. Symbols = {:?}",
+        lower_bound.with_timezone(&args.display_tz),
+        upper_bound.with_timezone(&args.display_tz),
+        args.resolutions.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        args.symbols
+    );
+    // ndjson is written while the periods are checked, the other formats once all are done
+    let mut ndjson: Option<Box<dyn Write>> = match (args.output, &args.output_file) {
+        (OutputFormat::Ndjson, Some(path)) => Some(Box::new(BufWriter::new(File::create(path)?))),
+        (OutputFormat::Ndjson, None) => Some(Box::new(std::io::stdout().lock())),
+        _ => None,
+    };
+    let interrupt = Interrupt::install();
+    let RunResults {
+        reports,
+        failed_symbols,
+    } = test_time_period_for_api(
+        &client,
+        &args,
+        &windows,
+        &mode,
+        ndjson
+            .as_mut()
+            .map(|writer| writer.as_mut() as &mut dyn Write),
+        Some(&interrupt),
+    )
+    .await?;
+    if interrupt.is_set() {
+        tracing::warn!(
+            "Interrupted: only {} period(s) were checked, the results below are partial",
+            reports.len()
+        );
+    }
+    if args.since_last_candle {
+        log_lag(&client, &args, &reports);
+    }
+
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let mut summary = Summary::from_reports(&reports);
+    for (symbol, error) in &failed_symbols {
+        summary.add_symbol_error(symbol, error);
+    }
+    summary.add_window_coverage(&reports, |symbol| windows.for_symbol(symbol));
+    if let Mode::Randomized { seed, .. } | Mode::Coverage { seed, .. } = mode {
+        summary.seed = Some(seed);
+    }
+    summary.latency = client.take_latency_stats();
+    summary.errors = client.take_error_counts();
+    if args.daily {
+        let mut daily = GapCalendar::new(args.display_tz);
+        for report in &reports {
+            daily.add(report, client.checked_candle_times(report));
+        }
+        summary.daily = Some(daily);
+    }
+    tracing::info!("{}", summary.in_zone(args.display_tz));
+    if let Some(metrics) = &metrics {
+        metrics.record_reports(&reports);
+    }
+    if let Some(archive) = &archive {
+        archive_run(archive, started, &args, &windows, &mode, &reports)?;
+    }
+
+    if let Some(webhook) = &webhook {
+        for alert in GapAlert::from_reports(&reports, args.alert_threshold) {
+            webhook.send(&alert).await;
+        }
+    }
+
+    match args.output {
+        OutputFormat::Text => {}
+        OutputFormat::Ndjson => write_ndjson_summary(&summary, ndjson.as_deref_mut().unwrap())?,
+        OutputFormat::Json => match &args.output_file {
+            Some(path) => write_json(&reports, &summary, std::fs::File::create(path)?)?,
+            None => write_json(&reports, &summary, std::io::stdout().lock())?,
+        },
+        OutputFormat::Csv => match &args.output_file {
+            Some(path) => write_csv(&reports, &summary, std::fs::File::create(path)?)?,
+            None => write_csv(&reports, &summary, std::io::stdout().lock())?,
+        },
+    }
+
+    if args.heatmap.is_some() || args.terminal_heatmap {
+        let mut heatmap = Heatmap::new();
+        for report in &reports {
+            heatmap.add(report, client.checked_candle_times(report));
+        }
+        if let Some(path) = &args.heatmap {
+            heatmap.write_svg(BufWriter::new(File::create(path)?))?;
+        }
+        if args.terminal_heatmap {
+            let terminal = console::Term::stderr();
+            let width = terminal
+                .size_checked()
+                .map_or(80, |(_, columns)| columns as usize);
+            heatmap.write_text(std::io::stderr().lock(), width, terminal.is_term())?;
+        }
+    }
+
+    if !failed_symbols.is_empty() {
+        anyhow::bail!("{} symbol(s) could not be checked", failed_symbols.len());
+    }
+    if summary.failed_periods > 0 {
+        anyhow::bail!("{} period(s) could not be fetched", summary.failed_periods);
+    }
+    if summary.missing > args.max_missing {
+        anyhow::bail!(
+            "{} missing candle(s) exceeds the allowed maximum of {}",
+            summary.missing,
+            args.max_missing
+        );
+    }
     Ok(())
 }
 
-enum Mode {
-    Simple,
-    Randomized { limit: usize },
+/// Warns about symbols the datafeed doesn't know and resolutions it doesn't advertise for them.
+/// Only warns: some datafeeds serve history for symbols their `symbols` endpoint can't resolve.
+async fn check_symbol_info(client: &ApiClient, args: &Args) {
+    for symbol in &args.symbols {
+        let info = match client.fetch_symbol_info(symbol).await {
+            Ok(info) => info,
+            Err(e) => {
+                tracing::warn!("[{}] Couldn't look up symbol info: {:#}", symbol, e);
+                continue;
+            }
+        };
+        tracing::debug!(
+            "[{}] Ticker {}, session {}, timezone {}, resolutions {:?}",
+            symbol,
+            info.ticker.as_deref().unwrap_or("?"),
+            info.session.as_deref().unwrap_or("?"),
+            info.timezone.as_deref().unwrap_or("?"),
+            info.supported_resolutions
+        );
+        for resolution in info.unsupported_resolutions(&args.resolutions) {
+            tracing::warn!(
+                "[{}] Resolution {} isn't among the supported resolutions {:?}, expect no candles",
+                symbol,
+                resolution,
+                info.supported_resolutions
+            );
+        }
+    }
 }
 
-async fn test_time_period_for_api(
-    lower_time_bound: DateTime<Utc>,
-    upper_time_bound: DateTime<Utc>,
-    resolution_minutes: u32,
-    mode: Mode,
+/// Candles requested by every benchmark request
+const BENCH_WINDOW_CANDLES: i32 = 100;
+
+async fn bench(
+    client: &ApiClient,
+    args: &Args,
+    upper_bound: DateTime<Utc>,
+    requests: usize,
 ) -> anyhow::Result<()> {
-    match mode {
-        Mode::Simple => {
-            test_api_for_period(
-                resolution_minutes,
-                lower_time_bound.timestamp(),
-                upper_time_bound.timestamp(),
-            )
-            .await?;
+    let symbol = &args.symbols[0];
+    let resolution = args.resolutions[0];
+    let from = upper_bound - resolution.approximate_duration() * BENCH_WINDOW_CANDLES;
+    tracing::info!(
+        "[{}] Benchmarking {} requests from {} to {} (resolution {}), {} at a time",
+        symbol,
+        requests,
+        from.with_timezone(&args.display_tz),
+        upper_bound.with_timezone(&args.display_tz),
+        resolution,
+        args.concurrency
+    );
+    let report = run_bench(
+        client,
+        symbol,
+        resolution,
+        from.timestamp(),
+        upper_bound.timestamp(),
+        requests,
+        args.concurrency,
+    )
+    .await;
+
+    tracing::info!("Benchmark results:\n{}", report);
+    match (args.output, &args.output_file) {
+        (OutputFormat::Json, Some(path)) => {
+            serde_json::to_writer_pretty(std::fs::File::create(path)?, &report)?
         }
-        Mode::Randomized { limit } => {
-            let periods = generate_random_time_periods(lower_time_bound, upper_time_bound, limit);
-            for (from, to) in periods {
-                test_api_for_period(resolution_minutes, from, to).await?;
-            }
+        (OutputFormat::Json, None) => {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &report)?
+        }
+        (OutputFormat::Csv | OutputFormat::Ndjson, _) => {
+            tracing::warn!("{:?} output is not supported for benchmarks", args.output)
         }
+        (OutputFormat::Text, _) => {}
     }
-
     Ok(())
 }
 
-/// ${BASE_URL}history?symbol=${symbolInfo.name}&resolution=${apiResolution}&from=${from}&to=${to}
-fn make_url(api_resolution: u32, from_ts: i64, to_ts: i64) -> String {
-    let base = std::env::var("BASE_URL").expect("BASE_URL env variable is missing");
-    format!(
-        "{}history?symbol=SOL/USDC&resolution={}&from={}&to={}",
-        base, api_resolution, from_ts, to_ts
-    )
+#[derive(Clone, Copy)]
+enum Snap {
+    Down,
+    Up,
+}
+
+/// Resolves a window bound. A date is moved to a candle boundary of the coarsest resolution
+/// checked, which is a boundary of the finer ones too, and the move is logged.
+fn snap_bound(
+    expression: TimeExpression,
+    now: DateTime<Utc>,
+    args: &Args,
+    snap: Snap,
+) -> anyhow::Result<DateTime<Utc>> {
+    let time = expression.resolve(now)?;
+    let coarsest = args
+        .resolutions
+        .iter()
+        .max_by_key(|resolution| resolution.approximate_duration());
+    let Some(&resolution) = coarsest.filter(|_| expression.is_date()) else {
+        return Ok(time);
+    };
+    let offset = TimeDelta::minutes(args.alignment_offset);
+    let snapped = match snap {
+        Snap::Down => last_normalized_time_at_or_before(time - offset, resolution, args.week_start),
+        Snap::Up => first_normalized_time_at_or_after(time - offset, resolution, args.week_start),
+    } + offset;
+    if snapped != time {
+        let (bound, direction) = match snap {
+            Snap::Down => ("start", "back"),
+            Snap::Up => ("end", "forward"),
+        };
+        tracing::info!(
+            "Moved the window {} {} {} to {}, a {} candle boundary",
+            bound,
+            time.date_naive(),
+            direction,
+            snapped,
+            resolution
+        );
+    }
+    Ok(snapped)
 }
 
-async fn test_api_for_period(resolution_minutes: u32, from: i64, to: i64) -> anyhow::Result<()> {
-    let url = make_url(resolution_minutes, from, to);
-    let from_utc = DateTime::from_timestamp(from, 0).unwrap();
-    let to_utc = DateTime::from_timestamp(to, 0).unwrap();
+/// The checked window of every symbol: `--from`/`--to`, unless `--symbol-window` overrides it.
+struct Windows {
+    default: (DateTime<Utc>, DateTime<Utc>),
+    overrides: HashMap<String, (DateTime<Utc>, DateTime<Utc>)>,
+}
 
-    log::info!("Getting API results from {} to {}", from_utc, to_utc);
-    log::debug!("Start timestamp = {}. End timestamp = {}", from, to);
-    log::debug!("Request url: {}", url);
+impl Windows {
+    /// Relative expressions are resolved against `now`. Dates are snapped outwards to candle
+    /// boundaries, so the window holds whole candles.
+    fn resolve(args: &Args, now: DateTime<Utc>) -> anyhow::Result<Self> {
+        let upper_bound = args
+            .to
+            .map_or(Ok(now), |to| snap_bound(to, now, args, Snap::Up))?;
+        let lower_bound = match args.from {
+            Some(from) => snap_bound(from, now, args, Snap::Down)?,
+            None => upper_bound.checked_sub_days(Days::new(14)).unwrap(), // From two weeks ago
+        };
+        anyhow::ensure!(
+            lower_bound < upper_bound,
+            "--from ({}) must be before --to ({})",
+            lower_bound,
+            upper_bound
+        );
 
-    let result: StructuredApiResult = reqwest::get(url).await?.json::<ApiResult>().await?.into();
-    if result.0.is_empty() {
-        log::info!("No results gotten for time period");
-        return Ok(());
+        let mut overrides = HashMap::new();
+        for window in &args.symbol_windows {
+            let lower = window.from.map_or(Ok(lower_bound), |from| {
+                snap_bound(from, now, args, Snap::Down)
+            })?;
+            let upper = window
+                .to
+                .map_or(Ok(upper_bound), |to| snap_bound(to, now, args, Snap::Up))?;
+            anyhow::ensure!(
+                lower < upper,
+                "The window of {} must start ({}) before it ends ({})",
+                window.symbol,
+                lower,
+                upper
+            );
+            overrides.insert(window.symbol.clone(), (lower, upper));
+        }
+        Ok(Windows {
+            default: (lower_bound, upper_bound),
+            overrides,
+        })
+    }
+
+    fn for_symbol(&self, symbol: &str) -> (DateTime<Utc>, DateTime<Utc>) {
+        self.overrides.get(symbol).copied().unwrap_or(self.default)
+    }
+
+    /// Starts every symbol's window at its first candle, when that's later. The window is left
+    /// alone when detection fails or finds no candles at all.
+    async fn trim_to_listings(&mut self, client: &ApiClient, args: &Args) {
+        // The finest resolution pins the first candle down most precisely
+        let resolution = *args
+            .resolutions
+            .iter()
+            .min_by_key(|resolution| resolution.approximate_duration())
+            .unwrap();
+        for symbol in &args.symbols {
+            let (lower, upper) = self.for_symbol(symbol);
+            let first = client
+                .detect_first_candle(symbol, resolution, lower.timestamp(), upper.timestamp())
+                .await;
+            match first {
+                Ok(Some(first)) if first > lower.timestamp() => {
+                    let first = DateTime::from_timestamp(first, 0).unwrap();
+                    tracing::debug!(
+                        "[{}] Checking from the first candle at {}",
+                        symbol,
+                        first.with_timezone(&args.display_tz)
+                    );
+                    self.overrides.insert(symbol.clone(), (first, upper));
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => tracing::warn!(
+                    "[{}] No candles between {} and {}, can't detect when it was listed",
+                    symbol,
+                    lower.with_timezone(&args.display_tz),
+                    upper.with_timezone(&args.display_tz)
+                ),
+                Err(e) => tracing::warn!("[{}] Failed to detect the first candle: {:#}", symbol, e),
+            }
+        }
+    }
+
+    /// Starts every symbol's window at its newest candle, so only what came after it is checked.
+    /// The window is left alone when detection fails or finds no candles at all.
+    async fn start_at_last_candles(&mut self, client: &ApiClient, args: &Args) {
+        // The finest resolution pins the newest candle down most precisely
+        let resolution = *args
+            .resolutions
+            .iter()
+            .min_by_key(|resolution| resolution.approximate_duration())
+            .unwrap();
+        for symbol in &args.symbols {
+            let (lower, upper) = self.for_symbol(symbol);
+            let last = client
+                .detect_last_candle(symbol, resolution, lower.timestamp(), upper.timestamp())
+                .await;
+            match last {
+                Ok(Some(last)) if last > lower.timestamp() => {
+                    let last = DateTime::from_timestamp(last, 0).unwrap();
+                    tracing::debug!(
+                        "[{}] Checking from the newest candle at {}",
+                        symbol,
+                        last.with_timezone(&args.display_tz)
+                    );
+                    self.overrides.insert(symbol.clone(), (last, upper));
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => tracing::warn!(
+                    "[{}] No candles between {} and {}, checking the whole window",
+                    symbol,
+                    lower.with_timezone(&args.display_tz),
+                    upper.with_timezone(&args.display_tz)
+                ),
+                Err(e) => tracing::warn!("[{}] Failed to find the newest candle: {:#}", symbol, e),
+            }
+        }
+    }
+
+    fn log_overrides(&self, args: &Args) {
+        for (symbol, (lower, upper)) in &self.overrides {
+            if args.symbols.contains(symbol) {
+                tracing::info!(
+                    "[{}] Checking its own window between {} and {}",
+                    symbol,
+                    lower.with_timezone(&args.display_tz),
+                    upper.with_timezone(&args.display_tz)
+                );
+            } else {
+                tracing::warn!("--symbol-window is set for {}, which isn't checked", symbol);
+            }
+        }
+    }
+}
+
+/// Re-checks the window every `interval` until interrupted, moving it along with relative
+/// `--from`/`--to`. Gaps are only logged when they appear or get filled, not on every check.
+async fn watch(
+    client: &ApiClient,
+    webhook: Option<&Webhook>,
+    metrics: Option<&Metrics>,
+    archive: Option<&ReportArchive>,
+    args: &Args,
+    mode: &Mode,
+    interval: TimeDelta,
+) -> anyhow::Result<()> {
+    tracing::info!(
+        "Watching {:?} every {}s, press Ctrl-C to stop",
+        args.symbols,
+        interval.num_seconds()
+    );
+    let mut ticker = tokio::time::interval(interval.to_std()?);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut shutdown = std::pin::pin!(tokio::signal::ctrl_c());
+
+    let mut known_gaps: HashSet<(String, Resolution, DateTime<Utc>)> = HashSet::new();
+    // Symbols and resolutions above the alert threshold in the previous check
+    let mut alerting: HashSet<(String, Resolution)> = HashSet::new();
+    let mut reports = vec![];
+    let mut checks = 0;
+    let mut latency = None;
+    let mut errors = ErrorCounts::default();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut shutdown => break,
+        }
+        let started = Utc::now();
+        let mut windows = Windows::resolve(args, started)?;
+        if args.detect_listing {
+            windows.trim_to_listings(client, args).await;
+        }
+        if args.since_last_candle {
+            windows.start_at_last_candles(client, args).await;
+        }
+        reports = tokio::select! {
+            results = test_time_period_for_api(client, args, &windows, mode, None, None) => results?.reports,
+            _ = &mut shutdown => break,
+        };
+        checks += 1;
+        if args.since_last_candle {
+            log_lag(client, args, &reports);
+        }
+        latency = client.take_latency_stats();
+        errors = client.take_error_counts();
+        if let Some(latency) = &latency {
+            tracing::debug!("{}", latency);
+        }
+        if let Some(metrics) = metrics {
+            metrics.record_reports(&reports);
+        }
+        if let Some(archive) = archive {
+            if let Err(e) = archive_run(archive, started, args, &windows, mode, &reports) {
+                tracing::warn!("Failed to archive the check: {:#}", e);
+            }
+        }
+
+        let mut gaps = HashSet::new();
+        for report in &reports {
+            if report.is_failed() {
+                tracing::warn!("{}", report.in_zone(args.display_tz));
+            }
+            let new: Vec<DateTime<Utc>> = report
+                .missing
+                .iter()
+                .filter(|time| {
+                    !known_gaps.contains(&(report.symbol.clone(), report.resolution, **time))
+                })
+                .copied()
+                .collect();
+            for gap in group_gaps(&new, report.resolution) {
+                tracing::warn!(
+                    "[{}] New gap (resolution {}): {}",
+                    report.symbol,
+                    report.resolution,
+                    gap.in_zone(args.display_tz)
+                );
+            }
+            gaps.extend(
+                report
+                    .missing
+                    .iter()
+                    .map(|time| (report.symbol.clone(), report.resolution, *time)),
+            );
+        }
+
+        // Gaps that moved out of the window aren't filled, they're just no longer checked
+        let mut filled: HashMap<(String, Resolution), Vec<DateTime<Utc>>> = HashMap::new();
+        for (symbol, resolution, time) in known_gaps.difference(&gaps) {
+            if *time >= windows.for_symbol(symbol).0 {
+                filled
+                    .entry((symbol.clone(), *resolution))
+                    .or_default()
+                    .push(*time);
+            }
+        }
+        for ((symbol, resolution), mut times) in filled {
+            times.sort_unstable();
+            for gap in group_gaps(&times, resolution) {
+                tracing::info!(
+                    "[{}] Gap filled (resolution {}): {}",
+                    symbol,
+                    resolution,
+                    gap.in_zone(args.display_tz)
+                );
+            }
+        }
+        known_gaps = gaps;
+
+        let alerts = GapAlert::from_reports(&reports, args.alert_threshold);
+        if let Some(webhook) = webhook {
+            for alert in &alerts {
+                if !alerting.contains(&(alert.symbol.clone(), alert.resolution)) {
+                    webhook.send(alert).await;
+                }
+            }
+        }
+        alerting = alerts
+            .into_iter()
+            .map(|alert| (alert.symbol, alert.resolution))
+            .collect();
     }
 
-    let mut next_normalized_time =
-        next_normalized_time_for_resolution(from_utc, resolution_minutes);
-    while next_normalized_time < to_utc {
-        let next_ts = next_normalized_time.timestamp();
-        match result.0.get(&next_ts) {
-            None => log::info!(
-                "{}: \x1b[31mX\x1b[0m No candle data found from API",
-                next_normalized_time
-            ),
-            Some(_) => log::info!(
-                "{}: \x1b[32m✓\x1b[0m Found candle data from API",
-                next_normalized_time
-            ),
+    let mut summary = Summary::from_reports(&reports);
+    summary.latency = latency;
+    summary.errors = errors;
+    tracing::info!(
+        "Stopped watching after {} check(s). Last check: {}",
+        checks,
+        summary.in_zone(args.display_tz)
+    );
+    Ok(())
+}
+
+/// Logs whether each symbol and resolution is caught up, or by how many candles it lags behind
+/// the end of the window. The newest candle of each symbol, that of its least caught up
+/// resolution, is remembered so the next check starts there.
+fn log_lag(client: &ApiClient, args: &Args, reports: &[GapReport]) {
+    let mut newest: HashMap<&str, DateTime<Utc>> = HashMap::new();
+    for report in reports.iter().filter(|report| !report.is_failed()) {
+        let missing: HashSet<&DateTime<Utc>> = report.missing.iter().collect();
+        let expected: Vec<DateTime<Utc>> = client.checked_candle_times(report).collect();
+        let present = expected.iter().rposition(|time| !missing.contains(time));
+        let lag = expected.len() - present.map_or(0, |index| index + 1);
+        let Some(present) = present else {
+            if lag == 0 {
+                tracing::info!(
+                    "[{}] Caught up (resolution {}): no candle has completed since {}",
+                    report.symbol,
+                    report.resolution,
+                    report.from.with_timezone(&args.display_tz)
+                );
+            } else {
+                tracing::warn!(
+                    "[{}] No candles since {} (resolution {}), lagging by {} candle(s)",
+                    report.symbol,
+                    report.from.with_timezone(&args.display_tz),
+                    report.resolution,
+                    lag
+                );
+            }
+            continue;
+        };
+        let last = expected[present];
+        newest
+            .entry(&report.symbol)
+            .and_modify(|newest| *newest = (*newest).min(last))
+            .or_insert(last);
+        if lag == 0 {
+            tracing::info!(
+                "[{}] Caught up (resolution {}): newest candle at {}",
+                report.symbol,
+                report.resolution,
+                last.with_timezone(&args.display_tz)
+            );
+        } else {
+            tracing::warn!(
+                "[{}] Lagging by {} candle(s) (resolution {}): newest candle at {}",
+                report.symbol,
+                lag,
+                report.resolution,
+                last.with_timezone(&args.display_tz)
+            );
         }
-        next_normalized_time =
-            next_normalized_time_for_resolution(next_normalized_time, resolution_minutes);
     }
+    for (symbol, last) in newest {
+        client.record_last_candle(symbol, last.timestamp());
+    }
+}
 
+/// Saves a summary of each symbol checked in the run started at `run_at`, then prunes runs past
+/// the retention. With `--diff-previous`, each symbol is first diffed against its latest
+/// archived run.
+fn archive_run(
+    archive: &ReportArchive,
+    run_at: DateTime<Utc>,
+    args: &Args,
+    windows: &Windows,
+    mode: &Mode,
+    reports: &[GapReport],
+) -> anyhow::Result<()> {
+    for symbol in &args.symbols {
+        let symbol_reports: Vec<GapReport> = reports
+            .iter()
+            .filter(|report| report.symbol == *symbol)
+            .cloned()
+            .collect();
+        if symbol_reports.is_empty() {
+            continue;
+        }
+        if args.diff_previous {
+            match archive.previous_run(symbol, run_at)? {
+                Some(previous) => {
+                    for diff in previous.diff(&symbol_reports) {
+                        if diff.is_empty() {
+                            tracing::info!("{}", diff.in_zone(args.display_tz));
+                        } else {
+                            tracing::warn!("{}", diff.in_zone(args.display_tz));
+                        }
+                    }
+                }
+                None => tracing::info!("[{}] No earlier run archived to diff against", symbol),
+            }
+        }
+        let mut summary = Summary::from_reports(&symbol_reports);
+        summary.add_window_coverage(&symbol_reports, |symbol| windows.for_symbol(symbol));
+        if let Mode::Randomized { seed, .. } | Mode::Coverage { seed, .. } = mode {
+            summary.seed = Some(*seed);
+        }
+        let path = archive.save(run_at, symbol, &summary, &symbol_reports)?;
+        tracing::debug!("[{}] Summary saved to {}", symbol, path.display());
+    }
+    let pruned = archive.prune(Utc::now())?;
+    if pruned > 0 {
+        tracing::info!(
+            "Deleted {} archived summaries past the retention from {}",
+            pruned,
+            archive.dir().display()
+        );
+    }
     Ok(())
 }
 
-fn next_normalized_time_for_resolution(
-    time: DateTime<Utc>,
-    resolution_minutes: u32,
-) -> DateTime<Utc> {
-    let mut final_time = NaiveDate::from_ymd_opt(time.year(), time.month(), time.day())
-        .unwrap()
-        .and_hms_opt(time.hour(), 0, 0)
-        .unwrap()
-        .and_local_timezone(Utc)
-        .unwrap();
-
-    while final_time.timestamp() <= time.timestamp() {
-        final_time = final_time
-            .checked_add_signed(TimeDelta::minutes(resolution_minutes as i64))
-            .unwrap();
+/// Logs go to stderr so json/csv reports on stdout stay parseable. A non-empty `RUST_LOG`
+/// decides verbosity when set, otherwise `level` (from `-v`/`-q`) does.
+fn init_tracing(format: LogFormat, level: Level, trace_http: bool) {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.is_empty() => EnvFilter::new(directives),
+        _ if trace_http => EnvFilter::new(format!("tv_debug={},tv_debug::http_trace=debug", level)),
+        _ => EnvFilter::new(format!("tv_debug={}", level)),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
     }
+}
 
-    final_time
+/// What a run found: the reports of every period checked, in symbol order, and the symbols whose
+/// check was abandoned, with why.
+#[derive(Debug, Default)]
+struct RunResults {
+    reports: Vec<GapReport>,
+    failed_symbols: Vec<(String, String)>,
 }
 
-fn generate_random_time_periods(
-    lower_time_bound: DateTime<Utc>,
-    upper_time_bound: DateTime<Utc>,
-    limit: usize,
-) -> Vec<(i64, i64)> {
-    let mut rng = rand::thread_rng();
-    let mut vec = Vec::with_capacity(limit);
+/// Checks every symbol, up to `--symbol-concurrency` at a time. An error or panic in one symbol's
+/// check is logged and recorded in `failed_symbols` without stopping the others, unless
+/// `--fail-fast` or `--strict-contiguity` asked for the run to stop.
+async fn test_time_period_for_api(
+    client: &ApiClient,
+    args: &Args,
+    windows: &Windows,
+    mode: &Mode,
+    mut ndjson: Option<&mut dyn Write>,
+    interrupt: Option<&Interrupt>,
+) -> anyhow::Result<RunResults> {
+    // Generated up front and in symbol order, so the periods don't depend on which symbol's
+    // check gets ahead
+    let mut rng = rng_for_mode(mode);
+    let mut symbol_periods = vec![];
+    for symbol in &args.symbols {
+        let (lower_time_bound, upper_time_bound) = windows.for_symbol(symbol);
+        let periods = periods_for_mode(mode, lower_time_bound, upper_time_bound, &mut rng)?;
+        if let Mode::Randomized { .. } = mode {
+            tracing::info!(
+                "[{}] Random periods per tenth of the window, oldest first: {:?}",
+                symbol,
+                period_distribution(&periods, lower_time_bound, upper_time_bound, 10)
+            );
+        }
+        symbol_periods.push((symbol, periods));
+    }
 
-    for _ in 0..limit {
-        let start = rng.gen_range(lower_time_bound.timestamp()..=upper_time_bound.timestamp());
-        let end = rng.gen_range(start..=upper_time_bound.timestamp());
+    if args.dry_run {
+        let mut planned_requests = 0;
+        for (symbol, periods) in &symbol_periods {
+            for &resolution in &args.resolutions {
+                print_planned_requests(client, symbol, resolution, periods);
+                planned_requests += periods
+                    .iter()
+                    .map(|(from, to)| client.chunk_period(resolution, *from, *to).len())
+                    .sum::<usize>();
+            }
+        }
+        println!("Dry run: {} request(s) planned", planned_requests);
+        return Ok(RunResults::default());
+    }
 
-        vec.push((start, end))
+    let planned_periods = symbol_periods
+        .iter()
+        .map(|(_, periods)| periods.len())
+        .sum::<usize>()
+        * args.resolutions.len();
+    // Hidden automatically when stderr isn't a terminal
+    let progress = ProgressBar::new(planned_periods as u64).with_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40} {pos}/{len} periods, ETA {eta} {msg}",
+        )
+        .unwrap(),
+    );
+    let mut checks = stream::iter(symbol_periods.iter().enumerate())
+        .map(|(index, (symbol, periods))| {
+            let check = check_symbol(client, args, symbol, periods, interrupt, &progress);
+            async move { (index, AssertUnwindSafe(check).catch_unwind().await) }
+        })
+        .buffer_unordered(args.symbol_concurrency);
+    let mut results = RunResults::default();
+    let mut symbol_reports = vec![];
+    while let Some((index, result)) = checks.next().await {
+        let symbol = symbol_periods[index].0;
+        let error = match result {
+            Ok(Ok(reports)) => {
+                if let Some(writer) = ndjson.as_deref_mut() {
+                    for report in &reports {
+                        let expected_times = client.checked_candle_times(report);
+                        write_ndjson_slots(report, expected_times, &mut *writer)?;
+                    }
+                }
+                symbol_reports.push((index, reports));
+                continue;
+            }
+            Ok(Err(e)) if args.fail_fast || args.strict_contiguity => {
+                progress.finish_and_clear();
+                return Err(e);
+            }
+            Ok(Err(e)) => format!("{:#}", e),
+            Err(panic) => panic_message(panic.as_ref()),
+        };
+        progress.suspend(|| tracing::error!("[{}] Check abandoned: {}", symbol, error));
+        results.failed_symbols.push((symbol.clone(), error));
     }
+    drop(checks);
+    progress.finish_and_clear();
+    // Symbols complete out of order, report them in the order given
+    symbol_reports.sort_by_key(|(index, _)| *index);
+    results.reports = symbol_reports
+        .into_iter()
+        .flat_map(|(_, reports)| reports)
+        .collect();
+    results
+        .failed_symbols
+        .sort_by_key(|(symbol, _)| args.symbols.iter().position(|s| s == symbol));
 
-    vec
+    if let Some(budget) = client
+        .request_budget()
+        .filter(|budget| budget.is_exhausted() && results.reports.len() < planned_periods)
+    {
+        tracing::warn!(
+            "Stopped by the budget of {} request(s): checked {} of {} planned period(s)",
+            budget.limit(),
+            results.reports.len(),
+            planned_periods
+        );
+    }
+    Ok(results)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct ApiResult {
-    s: String,
-    time: Vec<i64>,
-    close: Vec<f64>,
-    open: Vec<f64>,
-    high: Vec<f64>,
-    low: Vec<f64>,
-    volume: Vec<u64>,
+/// Checks `periods` of `symbol` at every resolution, and returns the reports chronologically
+/// within each resolution.
+async fn check_symbol(
+    client: &ApiClient,
+    args: &Args,
+    symbol: &str,
+    periods: &[(i64, i64)],
+    interrupt: Option<&Interrupt>,
+    progress: &ProgressBar,
+) -> anyhow::Result<Vec<GapReport>> {
+    let interrupted = || interrupt.is_some_and(Interrupt::is_set);
+    let mut reports = vec![];
+    for &resolution in &args.resolutions {
+        if interrupted() {
+            break;
+        }
+        progress.set_message(format!("{} {}", symbol, resolution));
+        // No new period is started once interrupted or out of requests
+        let mut checks = stream::iter(periods.iter().copied())
+            .take_while(|_| {
+                futures::future::ready(!interrupted() && !client.request_budget_exhausted())
+            })
+            .map(|(from, to)| async move {
+                let from_utc = DateTime::from_timestamp(from, 0).unwrap();
+                let to_utc = DateTime::from_timestamp(to, 0).unwrap();
+                match client.check_period(symbol, resolution, from, to).await {
+                    Ok(report) if args.strict_contiguity && !report.missing.is_empty() => {
+                        Err(anyhow::anyhow!(
+                            "[{}] Missing candle at {} (resolution {}) with strict contiguity on",
+                            symbol,
+                            report.missing[0].with_timezone(&args.display_tz),
+                            resolution
+                        ))
+                    }
+                    Ok(report) => Ok(Some(report)),
+                    // Not a problem with the period, it just wasn't checked
+                    Err(e) if e.is::<RequestBudgetExhausted>() => Ok(None),
+                    Err(e) if args.fail_fast => Err(e.context(format!(
+                        "[{}] Failed to fetch {} -> {} (resolution {})",
+                        symbol, from_utc, to_utc, resolution
+                    ))),
+                    Err(e) => Ok(Some(GapReport::failed(
+                        symbol, resolution, from_utc, to_utc, &e,
+                    ))),
+                }
+            })
+            .buffer_unordered(args.concurrency);
+        let mut grace_period_over = std::pin::pin!(Interrupt::grace_period_over(interrupt));
+        let mut period_reports = vec![];
+        loop {
+            let next = tokio::select! {
+                next = checks.next() => next,
+                _ = &mut grace_period_over => {
+                    progress.suspend(|| {
+                        tracing::warn!("[{}] Abandoning the periods still being checked", symbol)
+                    });
+                    break;
+                }
+            };
+            match next {
+                Some(Ok(report)) => {
+                    progress.inc(1);
+                    period_reports.extend(report);
+                }
+                Some(Err(e)) => return Err(e),
+                None => break,
+            }
+        }
+        // Requests complete out of order, report them chronologically
+        period_reports.sort_by_key(|report| (report.from, report.to));
+        progress.suspend(|| {
+            for report in &period_reports {
+                // Watch and diff modes only log changes between runs
+                if report.has_problems() && args.watch.is_none() && !args.diff_previous {
+                    tracing::info!("{}", report.in_zone(args.display_tz));
+                } else {
+                    tracing::debug!("{}", report.in_zone(args.display_tz));
+                }
+            }
+        });
+        reports.extend(period_reports);
+    }
+    Ok(reports)
 }
 
-type Time = i64;
-#[derive(Debug, Clone)]
-struct StructuredApiResult(HashMap<Time, CandleData>);
+/// The message a panic was raised with, when it's a string as with `panic!` and `expect`.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+    {
+        Some(message) => format!("panicked: {}", message),
+        None => "panicked".to_string(),
+    }
+}
 
+/// First Ctrl-C of a one-shot run. Checks stop starting, the ones in flight get
+/// `Interrupt::GRACE_PERIOD` to finish, and the partial results are reported. A second Ctrl-C
+/// quits immediately.
 #[derive(Debug, Clone)]
-struct CandleData {
-    close: f64,
-    open: f64,
-    high: f64,
-    low: f64,
-    volume: u64,
+struct Interrupt {
+    interrupted: tokio::sync::watch::Receiver<bool>,
 }
 
-impl From<ApiResult> for StructuredApiResult {
-    fn from(value: ApiResult) -> Self {
-        let mut hmap = HashMap::with_capacity(value.time.len());
-        for (i, time) in value.time.iter().enumerate() {
-            hmap.insert(
-                *time,
-                CandleData {
-                    close: value.close[i],
-                    open: value.open[i],
-                    high: value.high[i],
-                    low: value.low[i],
-                    volume: value.volume[i],
-                },
+impl Interrupt {
+    const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+    fn install() -> Self {
+        let (sender, interrupted) = tokio::sync::watch::channel(false);
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_err() {
+                return;
+            }
+            tracing::warn!(
+                "Interrupted, waiting up to {:?} for the checks in flight. Press Ctrl-C again \
+                 to quit immediately",
+                Self::GRACE_PERIOD
             );
+            let _ = sender.send(true);
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        });
+        Interrupt { interrupted }
+    }
+
+    fn is_set(&self) -> bool {
+        *self.interrupted.borrow()
+    }
+
+    /// Completes `GRACE_PERIOD` after the interruption, never without an `interrupt`.
+    async fn grace_period_over(interrupt: Option<&Interrupt>) {
+        let Some(interrupt) = interrupt else {
+            return std::future::pending().await;
+        };
+        let mut interrupted = interrupt.interrupted.clone();
+        if interrupted
+            .wait_for(|interrupted| *interrupted)
+            .await
+            .is_err()
+        {
+            // The handler is gone without an interruption
+            return std::future::pending().await;
         }
-        StructuredApiResult(hmap)
+        tokio::time::sleep(Self::GRACE_PERIOD).await;
     }
 }
 
-/*
-Known periods for which there is missing data(From viewing chart)
-- 12 May 21:00 13 May 02:00(No candles inbetween)
-- 13 May 05:00 13 May 08:00(No candles inbetween)
-- 13 May 10:00 13 May 12:00(No candles inbetween)
-*/
-async fn static_inspect() -> anyhow::Result<()> {
-    let may_12_2100 = NaiveDate::from_ymd_opt(2024, 05, 12)
-        .unwrap()
-        .and_hms_opt(21, 0, 0)
-        .unwrap()
-        .and_local_timezone(Utc)
-        .unwrap();
-    let may_12_2100_ts = may_12_2100.timestamp();
-    assert_eq!(may_12_2100_ts, 1715547600);
-
-    let may_13_0200 = NaiveDate::from_ymd_opt(2024, 05, 13)
-        .unwrap()
-        .and_hms_opt(02, 0, 0)
-        .unwrap()
-        .and_local_timezone(Utc)
-        .unwrap();
-    let may_13_0200_ts = may_13_0200.timestamp();
-    assert_eq!(may_13_0200_ts, 1715565600);
-
-    let may_13_0500 = NaiveDate::from_ymd_opt(2024, 05, 13)
-        .unwrap()
-        .and_hms_opt(05, 0, 0)
-        .unwrap()
-        .and_local_timezone(Utc)
-        .unwrap();
-    let may_12_0500_ts = may_13_0500.timestamp();
-    assert_eq!(may_12_0500_ts, 1715576400);
-
-    let may_13_0800 = NaiveDate::from_ymd_opt(2024, 05, 13)
-        .unwrap()
-        .and_hms_opt(08, 0, 0)
-        .unwrap()
-        .and_local_timezone(Utc)
-        .unwrap();
-    let may_13_0800_ts = may_13_0800.timestamp();
-    assert_eq!(may_13_0800_ts, 1715587200);
-
-    let may_13_1000 = NaiveDate::from_ymd_opt(2024, 05, 13)
-        .unwrap()
-        .and_hms_opt(10, 0, 0)
-        .unwrap()
-        .and_local_timezone(Utc)
-        .unwrap();
-    let may_13_1000_ts = may_13_1000.timestamp();
-    assert_eq!(may_13_1000_ts, 1715594400);
-
-    let may_13_1200 = NaiveDate::from_ymd_opt(2024, 05, 13)
-        .unwrap()
-        .and_hms_opt(12, 0, 0)
-        .unwrap()
-        .and_local_timezone(Utc)
-        .unwrap();
-    let may_13_1200_ts = may_13_1200.timestamp();
-    assert_eq!(may_13_1200_ts, 1715601600);
-
-    let url = make_url(60, may_12_2100_ts, may_13_0200_ts);
-    let result = reqwest::get(url).await?.json::<ApiResult>().await?;
-    println!("result: {:#?}", result);
-    println!(
-        "got bars for date: {}",
-        DateTime::from_timestamp(result.time[0], 0).unwrap()
-    );
+/// Every symbol draws from the same RNG in turn, so the periods only depend on the seed and the
+/// symbol order.
+fn rng_for_mode(mode: &Mode) -> StdRng {
+    match mode {
+        Mode::Randomized { seed, .. } | Mode::Coverage { seed, .. } => StdRng::seed_from_u64(*seed),
+        _ => StdRng::seed_from_u64(0),
+    }
+}
 
-    let url = make_url(60, may_12_0500_ts, may_13_0800_ts);
-    let result = reqwest::get(url).await?.json::<ApiResult>().await?;
-    println!("result: {:#?}", result);
-    println!(
-        "got bars for date: {}",
-        DateTime::from_timestamp(result.time[0], 0).unwrap()
-    );
+fn periods_for_mode(
+    mode: &Mode,
+    lower_time_bound: DateTime<Utc>,
+    upper_time_bound: DateTime<Utc>,
+    rng: &mut StdRng,
+) -> anyhow::Result<Vec<(i64, i64)>> {
+    match mode {
+        Mode::Simple => Ok(vec![(
+            lower_time_bound.timestamp(),
+            upper_time_bound.timestamp(),
+        )]),
+        Mode::Randomized {
+            limit,
+            min_span,
+            sampling,
+            ..
+        } => generate_sampled_periods(
+            lower_time_bound,
+            upper_time_bound,
+            *limit,
+            *min_span,
+            *sampling,
+            rng,
+        ),
+        Mode::Coverage { segments, .. } => Ok(generate_coverage_periods(
+            lower_time_bound,
+            upper_time_bound,
+            *segments,
+            rng,
+        )),
+        Mode::FromFile { periods } => Ok(periods.clone()),
+    }
+}
 
-    let url = make_url(60, may_13_1000_ts, may_13_1200_ts);
-    let result = reqwest::get(url).await?.json::<ApiResult>().await?;
-    println!("result: {:#?}", result);
-    println!(
-        "got bars for date: {}",
-        DateTime::from_timestamp(result.time[0], 0).unwrap()
-    );
+async fn compare_endpoints(
+    left: &ApiClient,
+    right: &ApiClient,
+    args: &Args,
+    windows: &Windows,
+    mode: &Mode,
+) -> anyhow::Result<()> {
+    let mut rng = rng_for_mode(mode);
+    let tolerance = Tolerance {
+        absolute: args.tolerance,
+        relative: args.relative_tolerance,
+    };
+    let mut reports = vec![];
+    for symbol in &args.symbols {
+        let (lower_time_bound, upper_time_bound) = windows.for_symbol(symbol);
+        let periods = periods_for_mode(mode, lower_time_bound, upper_time_bound, &mut rng)?;
+        for &resolution in &args.resolutions {
+            let mut period_reports: Vec<ComparisonReport> = stream::iter(periods.iter().copied())
+                .map(|(from, to)| {
+                    left.compare_period(right, symbol, resolution, from, to, tolerance)
+                })
+                .buffer_unordered(args.concurrency)
+                .try_collect()
+                .await?;
+            period_reports.sort_by_key(|report| (report.from, report.to));
+            for report in period_reports {
+                if report.differences() > 0 {
+                    tracing::info!("{}", report.in_zone(args.display_tz));
+                } else {
+                    tracing::debug!("{}", report.in_zone(args.display_tz));
+                }
+                reports.push(report);
+            }
+        }
+    }
+
+    write_comparisons(&reports, args)?;
+    let differences: usize = reports.iter().map(ComparisonReport::differences).sum();
+    if differences > 0 {
+        anyhow::bail!("Endpoints disagree on {} candle(s)", differences);
+    }
+    Ok(())
+}
+
+/// Checks that the `fine` candles of every period add up to the candles of each resolution.
+async fn cross_check_resolutions(
+    client: &ApiClient,
+    fine: Resolution,
+    args: &Args,
+    windows: &Windows,
+    mode: &Mode,
+) -> anyhow::Result<()> {
+    for coarse in &args.resolutions {
+        anyhow::ensure!(
+            fine.approximate_duration() < coarse.approximate_duration(),
+            "Can't aggregate resolution {} into {}, it must be lower",
+            fine,
+            coarse
+        );
+    }
+    let mut rng = rng_for_mode(mode);
+    let tolerance = Tolerance {
+        absolute: args.tolerance,
+        relative: args.relative_tolerance,
+    };
+    let mut reports = vec![];
+    for symbol in &args.symbols {
+        let (lower_time_bound, upper_time_bound) = windows.for_symbol(symbol);
+        let periods = periods_for_mode(mode, lower_time_bound, upper_time_bound, &mut rng)?;
+        for &coarse in &args.resolutions {
+            let mut period_reports: Vec<ComparisonReport> = stream::iter(periods.iter().copied())
+                .map(|(from, to)| {
+                    client.cross_check_period(symbol, fine, coarse, from, to, tolerance)
+                })
+                .buffer_unordered(args.concurrency)
+                .try_collect()
+                .await?;
+            period_reports.sort_by_key(|report| (report.from, report.to));
+            for report in period_reports {
+                if report.differences() > 0 {
+                    tracing::info!("{}", report.in_zone(args.display_tz));
+                } else {
+                    tracing::debug!("{}", report.in_zone(args.display_tz));
+                }
+                reports.push(report);
+            }
+        }
+    }
+
+    write_comparisons(&reports, args)?;
+    let differences: usize = reports.iter().map(ComparisonReport::differences).sum();
+    if differences > 0 {
+        anyhow::bail!(
+            "Aggregated candles disagree with the reported ones on {} candle(s)",
+            differences
+        );
+    }
+    Ok(())
+}
 
+fn write_comparisons(reports: &[ComparisonReport], args: &Args) -> anyhow::Result<()> {
+    match (args.output, &args.output_file) {
+        (OutputFormat::Json, Some(path)) => {
+            serde_json::to_writer_pretty(std::fs::File::create(path)?, &reports)?
+        }
+        (OutputFormat::Json, None) => {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &reports)?
+        }
+        (OutputFormat::Csv | OutputFormat::Ndjson, _) => {
+            tracing::warn!("{:?} output is not supported for comparisons", args.output)
+        }
+        (OutputFormat::Text, _) => {}
+    }
     Ok(())
 }
+
+fn print_planned_requests(
+    client: &ApiClient,
+    symbol: &str,
+    resolution: Resolution,
+    periods: &[(i64, i64)],
+) {
+    for (from, to) in periods {
+        let from_utc = DateTime::from_timestamp(*from, 0).unwrap();
+        let to_utc = DateTime::from_timestamp(*to, 0).unwrap();
+        let chunks = client.chunk_period(resolution, *from, *to);
+
+        println!("[{}] ({}, {}) {} -> {}", symbol, from, to, from_utc, to_utc);
+        for (chunk_from, chunk_to) in chunks {
+            println!(
+                "  GET {}",
+                client.history_url(symbol, resolution, chunk_from, chunk_to)
+            );
+        }
+        println!(
+            "  {} expected candle(s):",
+            client.expected_candle_count(resolution, from_utc, to_utc)
+        );
+        for time in client.iter_expected_candle_times(resolution, from_utc, to_utc) {
+            println!("    {}", time);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Context;
+use prometheus::{Encoder, GaugeVec, HistogramOpts, HistogramVec, IntGaugeVec, Opts, Registry};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::report::GapReport;
+use crate::resolution::Resolution;
+
+/// Prometheus metrics for the latest check, labeled by symbol and resolution, plus the latency
+/// of every `history` request.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    expected: IntGaugeVec,
+    present: IntGaugeVec,
+    missing: IntGaugeVec,
+    availability: GaugeVec,
+    request_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let labels = &["symbol", "resolution"];
+        let expected = IntGaugeVec::new(
+            Opts::new(
+                "candles_expected_total",
+                "Candles expected in the latest check",
+            ),
+            labels,
+        )
+        .unwrap();
+        let present = IntGaugeVec::new(
+            Opts::new(
+                "candles_present_total",
+                "Expected candles present in the latest check",
+            ),
+            labels,
+        )
+        .unwrap();
+        let missing = IntGaugeVec::new(
+            Opts::new(
+                "candles_missing_total",
+                "Expected candles missing in the latest check",
+            ),
+            labels,
+        )
+        .unwrap();
+        let availability = GaugeVec::new(
+            Opts::new(
+                "availability_ratio",
+                "Fraction of expected candles present in the latest check",
+            ),
+            labels,
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "history_request_duration_seconds",
+                "Latency of history requests, excluding retries and rate limiting",
+            ),
+            labels,
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(expected.clone())).unwrap();
+        registry.register(Box::new(present.clone())).unwrap();
+        registry.register(Box::new(missing.clone())).unwrap();
+        registry.register(Box::new(availability.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            expected,
+            present,
+            missing,
+            availability,
+            request_duration,
+        }
+    }
+
+    /// Replaces the candle gauges with the counts of `reports`. Failed periods are left out.
+    pub fn record_reports(&self, reports: &[GapReport]) {
+        let mut totals: HashMap<(&str, String), (usize, usize, usize)> = HashMap::new();
+        for report in reports.iter().filter(|report| !report.is_failed()) {
+            let total = totals
+                .entry((&report.symbol, report.resolution.to_string()))
+                .or_default();
+            total.0 += report.expected;
+            total.1 += report.present;
+            total.2 += report.missing.len();
+        }
+
+        self.expected.reset();
+        self.present.reset();
+        self.missing.reset();
+        self.availability.reset();
+        for ((symbol, resolution), (expected, present, missing)) in totals {
+            let labels = &[symbol, resolution.as_str()];
+            self.expected.with_label_values(labels).set(expected as i64);
+            self.present.with_label_values(labels).set(present as i64);
+            self.missing.with_label_values(labels).set(missing as i64);
+            self.availability
+                .with_label_values(labels)
+                .set(if expected == 0 {
+                    1.0
+                } else {
+                    present as f64 / expected as f64
+                });
+        }
+    }
+
+    pub fn observe_request(&self, symbol: &str, resolution: Resolution, duration: Duration) {
+        self.request_duration
+            .with_label_values(&[symbol, &resolution.to_string()])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        prometheus::TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    /// Serves the metrics to every HTTP request on `addr` from a background task. Only fails if
+    /// the address can't be bound.
+    pub async fn serve(&self, addr: SocketAddr) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind metrics server to {}", addr))?;
+        tracing::info!("Serving metrics on http://{}/metrics", addr);
+
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let mut stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::warn!("Failed to accept metrics connection: {}", e);
+                        continue;
+                    }
+                };
+                let body = metrics.encode();
+                tokio::spawn(async move {
+                    // The request itself doesn't matter, every path gets the metrics
+                    let mut request = [0; 1024];
+                    let _ = stream.read(&mut request).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        prometheus::TEXT_FORMAT,
+                        body.len(),
+                        body
+                    );
+                    if let Err(e) = stream.write_all(response.as_bytes()).await {
+                        tracing::debug!("Failed to write metrics response: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::report::GapReport;
+use crate::resolution::Resolution;
+use crate::summary::Summary;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human-readable log lines
+    #[default]
+    Text,
+    Json,
+    /// One row per missing candle, followed by per-symbol totals
+    Csv,
+    /// One JSON object per expected candle slot, written as each period is checked, and the
+    /// summary as the last line
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => anyhow::bail!(
+                "Unknown output format {:?}. Expected one of: text, json, csv, ndjson",
+                s
+            ),
+        }
+    }
+}
+
+/// Top-level document written by `--output json`.
+#[derive(Serialize, Debug)]
+pub struct JsonOutput<'a> {
+    pub summary: &'a Summary,
+    pub reports: Vec<JsonReport<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct JsonReport<'a> {
+    #[serde(flatten)]
+    pub report: &'a GapReport,
+    /// `present / expected`, 1.0 for periods with no expected candles and null for periods that
+    /// failed to fetch
+    pub availability: Option<f64>,
+}
+
+impl<'a> JsonOutput<'a> {
+    pub fn new(reports: &'a [GapReport], summary: &'a Summary) -> Self {
+        JsonOutput {
+            summary,
+            reports: reports
+                .iter()
+                .map(|report| JsonReport {
+                    report,
+                    availability: (!report.is_failed()).then(|| report.availability()),
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn write_json(
+    reports: &[GapReport],
+    summary: &Summary,
+    mut writer: impl Write,
+) -> anyhow::Result<()> {
+    serde_json::to_writer_pretty(&mut writer, &JsonOutput::new(reports, summary))?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+/// A line of `--output ndjson`.
+#[derive(Serialize, Debug)]
+pub struct NdjsonSlot<'a> {
+    pub symbol: &'a str,
+    pub resolution: Resolution,
+    pub time: DateTime<Utc>,
+    pub present: bool,
+}
+
+/// Writes one line per time in `expected_times`, which should be the times `report` was
+/// checked against. Failed periods have no slots and write nothing.
+pub fn write_ndjson_slots(
+    report: &GapReport,
+    expected_times: impl IntoIterator<Item = DateTime<Utc>>,
+    mut writer: impl Write,
+) -> anyhow::Result<()> {
+    if report.is_failed() {
+        return Ok(());
+    }
+    // Partial candles aren't present either
+    let missing: HashSet<&DateTime<Utc>> = report.missing.iter().chain(&report.partial).collect();
+    for time in expected_times {
+        let slot = NdjsonSlot {
+            symbol: &report.symbol,
+            resolution: report.resolution,
+            time,
+            present: !missing.contains(&time),
+        };
+        serde_json::to_writer(&mut writer, &slot)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The last line of `--output ndjson`, as `{"summary": {...}}`.
+pub fn write_ndjson_summary(summary: &Summary, mut writer: impl Write) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut writer, &serde_json::json!({ "summary": summary }))?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes one `symbol,resolution,missing_time_utc,missing_timestamp` row per missing candle,
+/// then a blank line and a
+/// `symbol,expected,present,missing,no_data_periods,failed_periods,status` section with
+/// per-symbol totals.
+pub fn write_csv(
+    reports: &[GapReport],
+    summary: &Summary,
+    mut writer: impl Write,
+) -> anyhow::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(&mut writer);
+    csv_writer.write_record([
+        "symbol",
+        "resolution",
+        "missing_time_utc",
+        "missing_timestamp",
+    ])?;
+    for report in reports {
+        for time in &report.missing {
+            csv_writer.write_record([
+                report.symbol.as_str(),
+                &report.resolution.to_string(),
+                &time.to_rfc3339_opts(SecondsFormat::Secs, true),
+                &time.timestamp().to_string(),
+            ])?;
+        }
+    }
+    csv_writer.flush()?;
+    drop(csv_writer);
+
+    writeln!(writer)?;
+    let mut csv_writer = csv::Writer::from_writer(&mut writer);
+    csv_writer.write_record([
+        "symbol",
+        "expected",
+        "present",
+        "missing",
+        "no_data_periods",
+        "failed_periods",
+        "status",
+    ])?;
+    for (symbol, totals) in &summary.per_symbol {
+        csv_writer.write_record([
+            symbol.clone(),
+            totals.expected.to_string(),
+            totals.present.to_string(),
+            totals.missing.to_string(),
+            totals.no_data_periods.to_string(),
+            totals.failed_periods.to_string(),
+            totals.status.to_string(),
+        ])?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,384 @@
+use std::path::Path;
+
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Simple,
+    /// `limit` random periods of at least `min_span`, drawn from an RNG seeded with `seed` so a
+    /// run can be repeated
+    Randomized {
+        limit: usize,
+        seed: u64,
+        min_span: TimeDelta,
+        sampling: Sampling,
+    },
+    /// The whole window split into `segments` contiguous periods, checked in an order
+    /// shuffled with `seed`
+    Coverage {
+        segments: usize,
+        seed: u64,
+    },
+    /// Exactly these `(from, to)` periods, usually loaded with `read_periods_file`
+    FromFile {
+        periods: Vec<(i64, i64)>,
+    },
+}
+
+/// How randomized mode spreads its periods over the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sampling {
+    /// Uniformly over the whole window
+    Uniform,
+    /// `periods` of them within the last `within` of the window, the rest uniformly over all of
+    /// it, so recent data gets checked more
+    Recent { within: TimeDelta, periods: usize },
+    /// The window split into a slice per period, each period drawn within its own slice, so no
+    /// part of the window is skipped by chance. Slices are never shorter than the minimum span,
+    /// so with more periods than fit, a slice gets several
+    Stratified,
+}
+
+/// Reads one `from,to` period per line. Both values can be unix timestamps or RFC3339 times,
+/// separated by a comma or whitespace. Blank lines and lines starting with `#` are ignored.
+///
+/// Lines that can't be parsed are logged with their line number and skipped, only a missing
+/// or unreadable file is an error.
+pub fn read_periods_file(path: &Path) -> anyhow::Result<Vec<(i64, i64)>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read periods file {}", path.display()))?;
+
+    let mut periods = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_period(line) {
+            Ok(period) => periods.push(period),
+            Err(e) => tracing::warn!(
+                "{}:{}: skipping invalid period {:?}: {}",
+                path.display(),
+                index + 1,
+                line,
+                e
+            ),
+        }
+    }
+    Ok(periods)
+}
+
+fn parse_period(line: &str) -> anyhow::Result<(i64, i64)> {
+    let fields: Vec<&str> = line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|field| !field.is_empty())
+        .collect();
+    let [from, to] = fields[..] else {
+        anyhow::bail!("expected 2 values, found {}", fields.len());
+    };
+    let (from, to) = (parse_time(from)?, parse_time(to)?);
+    if from >= to {
+        anyhow::bail!("from must be before to");
+    }
+    Ok((from, to))
+}
+
+fn parse_time(value: &str) -> anyhow::Result<i64> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp())
+        .with_context(|| {
+            format!(
+                "{:?} is neither a unix timestamp nor an RFC3339 time",
+                value
+            )
+        })
+}
+
+/// Random periods within the bounds, each at least `min_span` long. A `min_span` of one candle
+/// guarantees every period contains at least one expected candle. Fails if `min_span` doesn't
+/// fit between the bounds.
+///
+/// Exactly `limit` periods are returned, and every `(start, end)` satisfies
+/// `lower <= start`, `start + min_span <= end` and `end <= upper`. A zero `min_span` allows
+/// `start == end`.
+pub fn generate_random_time_periods(
+    lower_time_bound: DateTime<Utc>,
+    upper_time_bound: DateTime<Utc>,
+    limit: usize,
+    min_span: TimeDelta,
+    rng: &mut impl Rng,
+) -> anyhow::Result<Vec<(i64, i64)>> {
+    let min_span = min_span.num_seconds();
+    let latest_start = upper_time_bound.timestamp() - min_span;
+    if latest_start < lower_time_bound.timestamp() {
+        anyhow::bail!(
+            "A minimum period of {}s doesn't fit between {} and {}",
+            min_span,
+            lower_time_bound,
+            upper_time_bound
+        );
+    }
+    let mut vec = Vec::with_capacity(limit);
+
+    for _ in 0..limit {
+        let start = rng.gen_range(lower_time_bound.timestamp()..=latest_start);
+        let end = rng.gen_range(start + min_span..=upper_time_bound.timestamp());
+
+        vec.push((start, end))
+    }
+
+    Ok(vec)
+}
+
+/// `limit` random periods within the bounds, spread according to `sampling`. Each one is at
+/// least `min_span` long, see `generate_random_time_periods`.
+pub fn generate_sampled_periods(
+    lower_time_bound: DateTime<Utc>,
+    upper_time_bound: DateTime<Utc>,
+    limit: usize,
+    min_span: TimeDelta,
+    sampling: Sampling,
+    rng: &mut impl Rng,
+) -> anyhow::Result<Vec<(i64, i64)>> {
+    match sampling {
+        Sampling::Uniform => {
+            generate_random_time_periods(lower_time_bound, upper_time_bound, limit, min_span, rng)
+        }
+        Sampling::Recent { within, periods } => {
+            let recent = periods.min(limit);
+            let mut sampled = generate_random_time_periods(
+                (upper_time_bound - within).max(lower_time_bound),
+                upper_time_bound,
+                recent,
+                min_span,
+                rng,
+            )?;
+            sampled.extend(generate_random_time_periods(
+                lower_time_bound,
+                upper_time_bound,
+                limit - recent,
+                min_span,
+                rng,
+            )?);
+            Ok(sampled)
+        }
+        Sampling::Stratified => {
+            let lower = lower_time_bound.timestamp();
+            let length = upper_time_bound.timestamp() - lower;
+            let fitting = match min_span.num_seconds() {
+                0 => limit as i64,
+                min_span => length / min_span,
+            };
+            let slices = (limit as i64).min(fitting).max(1);
+            let mut sampled = Vec::with_capacity(limit);
+            for i in 0..slices {
+                let bound =
+                    |i: i64| DateTime::from_timestamp(lower + length * i / slices, 0).unwrap();
+                let periods = |i: i64| limit as i64 * i / slices;
+                sampled.extend(generate_random_time_periods(
+                    bound(i),
+                    bound(i + 1),
+                    (periods(i + 1) - periods(i)) as usize,
+                    min_span,
+                    rng,
+                )?);
+            }
+            Ok(sampled)
+        }
+    }
+}
+
+/// Number of periods whose midpoint falls into each of `buckets` equal slices of the window,
+/// oldest first. Shows how evenly random periods ended up spread.
+pub fn period_distribution(
+    periods: &[(i64, i64)],
+    lower_time_bound: DateTime<Utc>,
+    upper_time_bound: DateTime<Utc>,
+    buckets: usize,
+) -> Vec<usize> {
+    let lower = lower_time_bound.timestamp();
+    let length = (upper_time_bound.timestamp() - lower).max(1);
+    let mut counts = vec![0; buckets];
+    for (from, to) in periods {
+        let offset = (from + (to - from) / 2 - lower).clamp(0, length - 1);
+        counts[(offset as i128 * buckets as i128 / length as i128) as usize] += 1;
+    }
+    counts
+}
+
+/// Splits the window into `segments` adjacent periods of roughly equal length, so every
+/// candle slot is checked exactly once, then shuffles their order. Periods include both ends,
+/// so each one ends a second before the next starts.
+pub fn generate_coverage_periods(
+    lower_time_bound: DateTime<Utc>,
+    upper_time_bound: DateTime<Utc>,
+    segments: usize,
+    rng: &mut impl Rng,
+) -> Vec<(i64, i64)> {
+    let lower = lower_time_bound.timestamp();
+    let length = upper_time_bound.timestamp() - lower;
+    let segments = segments.clamp(1, length.max(1) as usize) as i64;
+
+    let mut periods: Vec<(i64, i64)> = (0..segments)
+        .map(|i| {
+            let end = if i + 1 == segments {
+                upper_time_bound.timestamp()
+            } else {
+                lower + length * (i + 1) / segments - 1
+            };
+            (lower + length * i / segments, end)
+        })
+        .collect();
+    periods.shuffle(rng);
+    periods
+}
+
+/// Fraction of the seconds in `[lower, upper]` that are covered by at least one of `periods`.
+/// Periods and the window include both ends.
+pub fn window_coverage(
+    periods: &[(i64, i64)],
+    lower_time_bound: DateTime<Utc>,
+    upper_time_bound: DateTime<Utc>,
+) -> f64 {
+    // Exclusive ends make the arithmetic below simpler
+    let (lower, upper) = (
+        lower_time_bound.timestamp(),
+        upper_time_bound.timestamp() + 1,
+    );
+    if upper <= lower {
+        return 1.0;
+    }
+
+    let mut periods: Vec<(i64, i64)> = periods
+        .iter()
+        .map(|(from, to)| ((*from).max(lower), (*to + 1).min(upper)))
+        .filter(|(from, to)| from < to)
+        .collect();
+    periods.sort_unstable();
+
+    let mut covered = 0;
+    let mut covered_until = lower;
+    for (from, to) in periods {
+        let from = from.max(covered_until);
+        if to > from {
+            covered += to - from;
+            covered_until = to;
+        }
+    }
+    covered as f64 / (upper - lower) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn stratified_periods_longer_than_their_slices_share_slices() {
+        // 20 daily periods over 14 days
+        let mut rng = StdRng::seed_from_u64(7);
+        let periods = generate_sampled_periods(
+            at(0),
+            at(14 * 86400),
+            20,
+            TimeDelta::days(1),
+            Sampling::Stratified,
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(periods.len(), 20);
+        for (start, end) in periods {
+            assert!(0 <= start && end <= 14 * 86400, "({}, {})", start, end);
+            assert!(end - start >= 86400, "({}, {})", start, end);
+        }
+
+        // When they fit, every period gets its own slice
+        let periods = generate_sampled_periods(
+            at(0),
+            at(14 * 86400),
+            7,
+            TimeDelta::days(1),
+            Sampling::Stratified,
+            &mut rng,
+        )
+        .unwrap();
+        for (i, (start, end)) in periods.into_iter().enumerate() {
+            let slice = 2 * 86400 * i as i64;
+            assert!(
+                slice <= start && end <= slice + 2 * 86400,
+                "({}, {})",
+                start,
+                end
+            );
+        }
+    }
+
+    #[test]
+    fn random_periods_are_at_least_the_minimum_span() {
+        let min_span = TimeDelta::hours(3);
+        let mut rng = StdRng::seed_from_u64(7);
+        let periods =
+            generate_random_time_periods(at(0), at(86400), 500, min_span, &mut rng).unwrap();
+        assert_eq!(periods.len(), 500);
+        for (start, end) in periods {
+            assert!(0 <= start && end <= 86400, "({}, {})", start, end);
+            assert!(
+                end - start >= min_span.num_seconds(),
+                "({}, {})",
+                start,
+                end
+            );
+        }
+    }
+
+    #[test]
+    fn a_minimum_span_longer_than_the_window_is_an_error() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert!(
+            generate_random_time_periods(at(0), at(3600), 1, TimeDelta::hours(2), &mut rng)
+                .is_err()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn random_periods_stay_within_bounds_and_span(
+            lower in -1_000_000_000i64..2_000_000_000,
+            length in 0i64..100_000_000,
+            min_span in 0i64..1_000_000,
+            limit in 0usize..50,
+            seed: u64,
+        ) {
+            prop_assume!(min_span <= length);
+            let upper = lower + length;
+            let mut rng = StdRng::seed_from_u64(seed);
+            let periods = generate_random_time_periods(
+                at(lower),
+                at(upper),
+                limit,
+                TimeDelta::seconds(min_span),
+                &mut rng,
+            )
+            .unwrap();
+            prop_assert_eq!(periods.len(), limit);
+            for (start, end) in periods {
+                prop_assert!(lower <= start);
+                prop_assert!(start + min_span <= end);
+                prop_assert!(end <= upper);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Spaces requests evenly so that no more than `requests_per_second` are started per second.
+/// Shared by all clones of an `ApiClient`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64) -> Self {
+        assert!(
+            requests_per_second > 0.0,
+            "requests_per_second must be positive"
+        );
+        RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until the caller is allowed to issue a request.
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self
+                .next_slot
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Holds back every request, including ones from other tasks, for at least `delay`.
+    /// Used when the server asks us to slow down.
+    pub fn pause_for(&self, delay: Duration) {
+        let mut next_slot = self
+            .next_slot
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *next_slot = (*next_slot).max(Instant::now() + delay);
+    }
+}
+
+/// Caps the number of requests sent over a run, retries included. Clones share the count, so
+/// one budget can cover several clients.
+#[derive(Debug, Clone)]
+pub struct RequestBudget {
+    limit: usize,
+    used: Arc<AtomicUsize>,
+}
+
+impl RequestBudget {
+    pub fn new(limit: usize) -> Self {
+        RequestBudget {
+            limit,
+            used: Arc::default(),
+        }
+    }
+
+    /// Takes one request out of the budget, failing without taking anything once it's used up.
+    pub fn spend(&self) -> Result<(), RequestBudgetExhausted> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used < self.limit).then_some(used + 1)
+            })
+            .map(|_| ())
+            .map_err(|_| RequestBudgetExhausted { limit: self.limit })
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.used() >= self.limit
+    }
+}
+
+/// A request wasn't sent because the run's `RequestBudget` was used up.
+#[derive(Debug)]
+pub struct RequestBudgetExhausted {
+    pub limit: usize,
+}
+
+impl fmt::Display for RequestBudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request budget of {} exhausted", self.limit)
+    }
+}
+
+impl std::error::Error for RequestBudgetExhausted {}
@@ -0,0 +1,412 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::client::HttpStatusError;
+use crate::display::{in_zone, timestamp_in_zone, DisplayInZone};
+use crate::history::{StructuredApiResult, Time};
+use crate::resolution::Resolution;
+use crate::validation::{AnomalyKind, DuplicateCandle, FlatRun};
+use crate::volume::VolumeStats;
+
+/// Whether a period could be checked, and whether the API had anything for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PeriodStatus {
+    Ok,
+    /// The API answered `no_data` for the whole period. Every expected candle is missing.
+    NoData,
+    /// The period couldn't be fetched, so nothing is known about its candles.
+    FetchError {
+        reason: String,
+        /// Status of the last response, when the server answered with a non-2xx status
+        #[serde(skip_serializing_if = "Option::is_none")]
+        http_status: Option<u16>,
+    },
+}
+
+/// Outcome of checking a single period for a symbol. A period includes both `from` and `to`:
+/// a candle starting at either is expected.
+///
+/// Serialized field-for-field in JSON output, with times as RFC3339 strings and the
+/// resolution in its API wire form.
+#[derive(Debug, Clone, Serialize)]
+pub struct GapReport {
+    pub symbol: String,
+    pub resolution: Resolution,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    #[serde(flatten)]
+    pub status: PeriodStatus,
+    /// Number of candle slots in `[from, to]`. Zero for periods that failed to fetch.
+    pub expected: usize,
+    pub present: usize,
+    /// Expected candles that were returned with a `null` or NaN price. They count as neither
+    /// present nor missing.
+    pub partial: Vec<DateTime<Utc>>,
+    /// Candles in the responses, counting duplicates within one and candles outside the
+    /// expected slots. A candle in two overlapping chunks counts once.
+    pub returned: usize,
+    pub missing: Vec<DateTime<Utc>>,
+    /// `(timestamp, kind)` pairs. Only populated when OHLC validation is enabled.
+    pub anomalies: Vec<(Time, AnomalyKind)>,
+    /// Runs of identical flat candles that look like filler. Only populated when flat candle
+    /// detection is enabled.
+    pub suspected_filler: Vec<FlatRun>,
+    /// Timestamps the API returned more than once in a response, or with different values in
+    /// two overlapping chunks
+    pub duplicates: Vec<DuplicateCandle>,
+    /// Adjacent `(earlier, later)` timestamps in response order where `later` isn't greater
+    pub out_of_order: Vec<(Time, Time)>,
+    /// Returned timestamps before `from` or after `to` of the request. They're kept with the
+    /// other candles but never expected.
+    pub outside_window: Vec<Time>,
+    /// `nextTime` hints from `no_data` responses that were followed with another request
+    pub followed_next_times: Vec<Time>,
+    /// Trailing candles that hadn't closed yet when the period was checked, and weren't
+    /// expected. Only non-zero when the current candle is excluded.
+    pub excluded_current: usize,
+    /// Whether a response came without a `volume` column. Its candles count as present, but
+    /// volume checks are skipped.
+    pub volume_unavailable: bool,
+    /// Base URL of the endpoint the period was checked with, when fallback endpoints are set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub served_by: Option<String>,
+    /// Number of candles requested with `countback`, when the period was queried that way.
+    /// `from` is then the earliest candle returned, if that's before the requested start.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countback: Option<usize>,
+    /// Only populated when volume statistics are enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<VolumeStats>,
+}
+
+impl GapReport {
+    /// Records which of `expected_times` are absent from `result`. The times are consumed one
+    /// at a time, so they can come from `ApiClient::iter_expected_candle_times` without being
+    /// collected first.
+    pub fn from_result(
+        symbol: &str,
+        resolution: Resolution,
+        expected_times: impl IntoIterator<Item = DateTime<Utc>>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        result: &StructuredApiResult,
+    ) -> Self {
+        let mut expected = 0;
+        let (mut missing, mut partial) = (vec![], vec![]);
+        for time in expected_times {
+            expected += 1;
+            match result.0.get(&time.timestamp()) {
+                None => missing.push(time),
+                Some(candle) if candle.is_partial() => partial.push(time),
+                Some(_) => {}
+            }
+        }
+        let present = expected - missing.len() - partial.len();
+        let returned = result.0.len();
+
+        GapReport {
+            symbol: symbol.to_string(),
+            resolution,
+            from,
+            to,
+            status: PeriodStatus::Ok,
+            expected,
+            present,
+            partial,
+            returned,
+            missing,
+            anomalies: vec![],
+            suspected_filler: vec![],
+            duplicates: vec![],
+            out_of_order: vec![],
+            outside_window: vec![],
+            followed_next_times: vec![],
+            excluded_current: 0,
+            volume_unavailable: false,
+            served_by: None,
+            countback: None,
+            volume: None,
+        }
+    }
+
+    /// Report for a period whose candles couldn't be fetched because of `error`.
+    pub fn failed(
+        symbol: &str,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        error: &anyhow::Error,
+    ) -> Self {
+        GapReport {
+            symbol: symbol.to_string(),
+            resolution,
+            from,
+            to,
+            status: PeriodStatus::FetchError {
+                reason: format!("{:#}", error),
+                http_status: error
+                    .downcast_ref::<HttpStatusError>()
+                    .map(|error| error.status.as_u16()),
+            },
+            expected: 0,
+            present: 0,
+            partial: vec![],
+            returned: 0,
+            missing: vec![],
+            anomalies: vec![],
+            suspected_filler: vec![],
+            duplicates: vec![],
+            out_of_order: vec![],
+            outside_window: vec![],
+            followed_next_times: vec![],
+            excluded_current: 0,
+            volume_unavailable: false,
+            served_by: None,
+            countback: None,
+            volume: None,
+        }
+    }
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self.status, PeriodStatus::FetchError { .. })
+    }
+
+    /// Whether anything about the period deserves attention: missing candles, a failed fetch,
+    /// `no_data`, or any of the candle checks flagging something.
+    pub fn has_problems(&self) -> bool {
+        self.status != PeriodStatus::Ok
+            || !self.missing.is_empty()
+            || !self.partial.is_empty()
+            || !self.anomalies.is_empty()
+            || !self.suspected_filler.is_empty()
+            || !self.duplicates.is_empty()
+            || !self.out_of_order.is_empty()
+            || !self.outside_window.is_empty()
+            || self
+                .volume
+                .as_ref()
+                .is_some_and(|volume| !volume.zero_volume.is_empty() || !volume.outliers.is_empty())
+    }
+
+    pub fn gap_ranges(&self) -> Vec<GapRange> {
+        group_gaps(&self.missing, self.resolution)
+    }
+
+    /// Fraction of expected candles that were present. An empty period counts as fully available.
+    pub fn availability(&self) -> f64 {
+        if self.expected == 0 {
+            return 1.0;
+        }
+        self.present as f64 / self.expected as f64
+    }
+}
+
+/// A run of consecutive missing candles. `start` and `end` are the first and last missing
+/// candle times, so a single missing candle has `start == end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct GapRange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub count: usize,
+}
+
+/// Collapses sorted missing candle times into ranges of consecutive candles.
+pub fn group_gaps(missing: &[DateTime<Utc>], resolution: Resolution) -> Vec<GapRange> {
+    let mut ranges: Vec<GapRange> = vec![];
+    for time in missing {
+        match ranges.last_mut() {
+            Some(range) if resolution.advance(range.end) == *time => {
+                range.end = *time;
+                range.count += 1;
+            }
+            _ => ranges.push(GapRange {
+                start: *time,
+                end: *time,
+                count: 1,
+            }),
+        }
+    }
+    ranges
+}
+
+impl fmt::Display for GapRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_in(f, Tz::UTC)
+    }
+}
+
+impl DisplayInZone for GapRange {
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, tz: Tz) -> fmt::Result {
+        const FORMAT: &str = "%Y-%m-%d %H:%M";
+        if self.count == 1 {
+            write!(
+                f,
+                "{} (1 candle missing)",
+                in_zone(self.start, tz).format(FORMAT)
+            )
+        } else {
+            write!(
+                f,
+                "{} → {} ({} candles missing)",
+                in_zone(self.start, tz).format(FORMAT),
+                in_zone(self.end, tz).format(FORMAT),
+                self.count
+            )
+        }
+    }
+}
+
+impl fmt::Display for GapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_in(f, Tz::UTC)
+    }
+}
+
+impl DisplayInZone for GapReport {
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, tz: Tz) -> fmt::Result {
+        if let PeriodStatus::FetchError { reason, .. } = &self.status {
+            return write!(
+                f,
+                "[{}] {} -> {} (resolution {}): fetch failed: {}",
+                self.symbol,
+                in_zone(self.from, tz),
+                in_zone(self.to, tz),
+                self.resolution,
+                reason
+            );
+        }
+        write!(
+            f,
+            "[{}] {} -> {} (resolution {}): {}/{} candles present ({:.2}%){}",
+            self.symbol,
+            in_zone(self.from, tz),
+            in_zone(self.to, tz),
+            self.resolution,
+            self.present,
+            self.expected,
+            self.availability() * 100.0,
+            if self.status == PeriodStatus::NoData {
+                ", API reported no_data"
+            } else {
+                ""
+            }
+        )?;
+        for gap in self.gap_ranges() {
+            write!(f, "\n[{}] X {}", self.symbol, gap.in_zone(tz))?;
+        }
+        for time in &self.partial {
+            write!(
+                f,
+                "\n[{}] {}: ! Partial candle with a missing price",
+                self.symbol,
+                in_zone(*time, tz)
+            )?;
+        }
+        for (time, kind) in &self.anomalies {
+            let time = timestamp_in_zone(*time, tz);
+            write!(
+                f,
+                "\n[{}] {}: ! Anomalous candle: {}",
+                self.symbol, time, kind
+            )?;
+        }
+        for run in &self.suspected_filler {
+            write!(
+                f,
+                "\n[{}] ! Suspected filler: {} flat candle(s) at {} from {} to {}",
+                self.symbol,
+                run.candles,
+                run.price,
+                timestamp_in_zone(run.start, tz),
+                timestamp_in_zone(run.end, tz)
+            )?;
+        }
+        for duplicate in &self.duplicates {
+            let time = timestamp_in_zone(duplicate.time, tz);
+            write!(
+                f,
+                "\n[{}] {}: ! Duplicate candle{}",
+                self.symbol,
+                time,
+                if duplicate.is_conflicting() {
+                    " with conflicting values"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        for (earlier, later) in &self.out_of_order {
+            write!(
+                f,
+                "\n[{}] ! Out-of-order timestamps: {} followed by {}",
+                self.symbol,
+                timestamp_in_zone(*earlier, tz),
+                timestamp_in_zone(*later, tz)
+            )?;
+        }
+        for time in &self.outside_window {
+            write!(
+                f,
+                "\n[{}] {}: ! Candle outside the requested window",
+                self.symbol,
+                timestamp_in_zone(*time, tz)
+            )?;
+        }
+        for next_time in &self.followed_next_times {
+            write!(
+                f,
+                "\n[{}] ! No data before nextTime {}, data checked from there on",
+                self.symbol,
+                timestamp_in_zone(*next_time, tz)
+            )?;
+        }
+        if self.excluded_current > 0 {
+            write!(
+                f,
+                "\n[{}] Excluded {} still-forming candle(s) at the end",
+                self.symbol, self.excluded_current
+            )?;
+        }
+        if self.volume_unavailable {
+            write!(
+                f,
+                "\n[{}] Volume unavailable, volume checks skipped",
+                self.symbol
+            )?;
+        }
+        if let Some(served_by) = &self.served_by {
+            write!(f, "\n[{}] Served by {}", self.symbol, served_by)?;
+        }
+        if let Some(countback) = self.countback {
+            write!(
+                f,
+                "\n[{}] Queried with countback={}, window reconstructed from the returned candles",
+                self.symbol, countback
+            )?;
+        }
+        if let Some(volume) = &self.volume {
+            write!(f, "\n[{}] {}", self.symbol, volume)?;
+            for time in &volume.zero_volume {
+                write!(
+                    f,
+                    "\n[{}] {}: ! Zero volume",
+                    self.symbol,
+                    timestamp_in_zone(*time, tz)
+                )?;
+            }
+            for outlier in &volume.outliers {
+                write!(
+                    f,
+                    "\n[{}] ! Volume outlier at {}",
+                    self.symbol,
+                    outlier.in_zone(tz)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,458 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, TimeDelta, Utc, Weekday};
+use serde::{Serialize, Serializer};
+
+/// Candle resolution as understood by the UDF `history` endpoint.
+///
+/// Minute and hour resolutions are sent as a number of minutes (`"60"`, `"240"`),
+/// the calendar-based ones as `"D"`, `"W"` and `"M"` with an optional multiplier (`"3D"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    Minutes(u32),
+    Hours(u32),
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+}
+
+impl Resolution {
+    /// Wire form used in the `resolution` query parameter.
+    pub fn to_api_string(self) -> String {
+        match self {
+            Resolution::Minutes(n) => n.to_string(),
+            Resolution::Hours(n) => (n * 60).to_string(),
+            Resolution::Days(1) => "D".to_string(),
+            Resolution::Days(n) => format!("{}D", n),
+            Resolution::Weeks(1) => "W".to_string(),
+            Resolution::Weeks(n) => format!("{}W", n),
+            Resolution::Months(1) => "M".to_string(),
+            Resolution::Months(n) => format!("{}M", n),
+        }
+    }
+
+    /// Length of one candle. Calendar resolutions use their longest possible length, so a
+    /// window of `n * approximate_duration()` never holds more than `n` candles.
+    pub fn approximate_duration(self) -> TimeDelta {
+        match self {
+            Resolution::Minutes(n) => TimeDelta::minutes(n as i64),
+            Resolution::Hours(n) => TimeDelta::hours(n as i64),
+            Resolution::Days(n) => TimeDelta::days(n as i64),
+            Resolution::Weeks(n) => TimeDelta::weeks(n as i64),
+            Resolution::Months(n) => TimeDelta::days(31 * n as i64),
+        }
+    }
+
+    /// Moves `time` forward by one candle. Calendar resolutions step by calendar
+    /// days/months, so months of different lengths are handled correctly.
+    pub fn advance(self, time: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Resolution::Minutes(n) => time + TimeDelta::minutes(n as i64),
+            Resolution::Hours(n) => time + TimeDelta::hours(n as i64),
+            Resolution::Days(n) => time.checked_add_days(Days::new(n as u64)).unwrap(),
+            Resolution::Weeks(n) => time.checked_add_days(Days::new(7 * n as u64)).unwrap(),
+            Resolution::Months(n) => time.checked_add_months(Months::new(n)).unwrap(),
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_api_string())
+    }
+}
+
+impl Serialize for Resolution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (count, unit) = s.split_at(unit_start);
+        let count = match count {
+            "" => 1,
+            count => count.parse::<u32>()?,
+        };
+        if count == 0 {
+            anyhow::bail!("Resolution must be greater than zero: {:?}", s);
+        }
+
+        match unit {
+            "" => Ok(Resolution::Minutes(count)),
+            // Sent as minutes, so it has to fit in them
+            "H" | "h" if count.checked_mul(60).is_none() => {
+                anyhow::bail!("Resolution is too large: {:?}", s)
+            }
+            "H" | "h" => Ok(Resolution::Hours(count)),
+            "D" | "d" => Ok(Resolution::Days(count)),
+            "W" | "w" => Ok(Resolution::Weeks(count)),
+            // Lowercase `m` is deliberately not accepted, it reads too much like minutes
+            "M" => Ok(Resolution::Months(count)),
+            _ => anyhow::bail!("Unrecognized resolution: {:?}", s),
+        }
+    }
+}
+
+/// Returns the first candle boundary strictly after `time`.
+///
+/// Intraday and daily candles are aligned to multiples of the resolution since the Unix epoch,
+/// so e.g. 90 minute candles don't restart every hour. Weekly candles are aligned to midnight
+/// UTC on `week_start`, monthly candles to the first day of the month.
+pub fn next_normalized_time_for_resolution(
+    time: DateTime<Utc>,
+    resolution: Resolution,
+    week_start: Weekday,
+) -> DateTime<Utc> {
+    let start_of_day = NaiveDate::from_ymd_opt(time.year(), time.month(), time.day())
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_local_timezone(Utc)
+        .unwrap();
+
+    let mut final_time = match resolution {
+        Resolution::Minutes(_) | Resolution::Hours(_) | Resolution::Days(_) => {
+            let step = resolution.approximate_duration().num_seconds();
+            DateTime::from_timestamp(time.timestamp().div_euclid(step) * step, 0).unwrap()
+        }
+        Resolution::Weeks(_) => {
+            let days_since_week_start = time.weekday().days_since(week_start);
+            start_of_day
+                .checked_sub_days(Days::new(days_since_week_start as u64))
+                .unwrap()
+        }
+        Resolution::Months(_) => start_of_day.with_day(1).unwrap(),
+    };
+
+    while final_time.timestamp() <= time.timestamp() {
+        final_time = resolution.advance(final_time);
+    }
+
+    final_time
+}
+
+/// Returns the first candle boundary at or after `time`, so a `time` that is itself a boundary
+/// is returned unchanged. Sub-second precision is ignored, like it is by the API.
+pub fn first_normalized_time_at_or_after(
+    time: DateTime<Utc>,
+    resolution: Resolution,
+    week_start: Weekday,
+) -> DateTime<Utc> {
+    let just_before = DateTime::from_timestamp(time.timestamp() - 1, 0).unwrap();
+    next_normalized_time_for_resolution(just_before, resolution, week_start)
+}
+
+/// Returns the start of the candle `time` falls in, the last candle boundary at or before it.
+pub fn last_normalized_time_at_or_before(
+    time: DateTime<Utc>,
+    resolution: Resolution,
+    week_start: Weekday,
+) -> DateTime<Utc> {
+    // No candle is longer than its approximate duration, so this is at most one candle back
+    let mut boundary = next_normalized_time_for_resolution(
+        time - resolution.approximate_duration(),
+        resolution,
+        week_start,
+    );
+    while resolution.advance(boundary) <= time {
+        boundary = resolution.advance(boundary);
+    }
+    boundary
+}
+
+/// Every normalized candle time in `[from, to]`. Both ends are inclusive, like the `from` and
+/// `to` of a UDF `history` request, so candles starting exactly at `from` or `to` are expected.
+pub fn expected_candle_times(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    resolution: Resolution,
+    week_start: Weekday,
+) -> Vec<DateTime<Utc>> {
+    candle_times(from, to, resolution, week_start).collect()
+}
+
+/// The times of `expected_candle_times` in ascending order, computed as they're iterated. Use
+/// this to count candles without collecting them.
+pub fn candle_times(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    resolution: Resolution,
+    week_start: Weekday,
+) -> impl Iterator<Item = DateTime<Utc>> {
+    let first = first_normalized_time_at_or_after(from, resolution, week_start);
+    std::iter::successors(Some(first), move |time| {
+        Some(next_normalized_time_for_resolution(
+            *time, resolution, week_start,
+        ))
+    })
+    .take_while(move |time| *time <= to)
+}
+
+/// Advertised resolutions as sent by a datafeed, e.g. `"60"` or `"1D"`, parsed and without
+/// equivalent repeats. Ones that can't be parsed are skipped.
+pub fn parse_advertised_resolutions(advertised: &[String]) -> Vec<Resolution> {
+    let mut resolutions: Vec<Resolution> = vec![];
+    for resolution in advertised {
+        match resolution.parse::<Resolution>() {
+            Ok(resolution)
+                if !resolutions
+                    .iter()
+                    .any(|seen| seen.to_api_string() == resolution.to_api_string()) =>
+            {
+                resolutions.push(resolution)
+            }
+            Ok(_) => {}
+            Err(e) => tracing::debug!("Ignoring advertised resolution {:?}: {}", resolution, e),
+        }
+    }
+    resolutions
+}
+
+/// Those of `requested` missing from the `advertised` list. Empty when nothing is advertised,
+/// since nothing can be concluded then. Compared in wire form, so `"1D"` matches `D`.
+pub fn unadvertised_resolutions(
+    advertised: &[String],
+    requested: &[Resolution],
+) -> Vec<Resolution> {
+    if advertised.is_empty() {
+        return vec![];
+    }
+    let advertised: Vec<String> = parse_advertised_resolutions(advertised)
+        .into_iter()
+        .map(Resolution::to_api_string)
+        .collect();
+    requested
+        .iter()
+        .filter(|resolution| !advertised.contains(&resolution.to_api_string()))
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_minute_and_calendar_resolutions() {
+        assert_eq!("60".parse::<Resolution>().unwrap(), Resolution::Minutes(60));
+        assert_eq!("4H".parse::<Resolution>().unwrap(), Resolution::Hours(4));
+        assert_eq!("D".parse::<Resolution>().unwrap(), Resolution::Days(1));
+        assert_eq!("3D".parse::<Resolution>().unwrap(), Resolution::Days(3));
+        assert_eq!("W".parse::<Resolution>().unwrap(), Resolution::Weeks(1));
+        assert_eq!("M".parse::<Resolution>().unwrap(), Resolution::Months(1));
+        assert_eq!(Resolution::Hours(4).to_api_string(), "240");
+        assert_eq!(Resolution::Days(1).to_api_string(), "D");
+    }
+
+    #[test]
+    fn rejects_invalid_resolutions() {
+        for invalid in ["0", "0D", "5m", "X", "99999999H"] {
+            assert!(invalid.parse::<Resolution>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn next_boundary_is_a_multiple_of_the_resolution_since_the_epoch() {
+        let time = utc("2024-05-12T13:47:31Z");
+        for (resolution, next) in [
+            ("1", "2024-05-12T13:48:00Z"),
+            ("5", "2024-05-12T13:50:00Z"),
+            ("15", "2024-05-12T14:00:00Z"),
+            ("60", "2024-05-12T14:00:00Z"),
+            ("240", "2024-05-12T16:00:00Z"),
+            ("D", "2024-05-13T00:00:00Z"),
+        ] {
+            let resolution: Resolution = resolution.parse().unwrap();
+            assert_eq!(
+                next_normalized_time_for_resolution(time, resolution, Weekday::Mon),
+                utc(next),
+                "{}",
+                resolution
+            );
+        }
+        // 90 minute candles don't restart every hour
+        assert_eq!(
+            next_normalized_time_for_resolution(time, Resolution::Minutes(90), Weekday::Mon),
+            utc("2024-05-12T15:00:00Z")
+        );
+    }
+
+    #[test]
+    fn next_boundary_across_month_ends_and_leap_days() {
+        for (resolution, time, next) in [
+            (
+                Resolution::Minutes(60),
+                "2024-05-12T21:30:00Z",
+                "2024-05-12T22:00:00Z",
+            ),
+            (
+                Resolution::Minutes(60),
+                "2024-05-12T22:00:00Z",
+                "2024-05-12T23:00:00Z",
+            ),
+            (
+                Resolution::Hours(4),
+                "2024-12-31T23:00:00Z",
+                "2025-01-01T00:00:00Z",
+            ),
+            (
+                Resolution::Days(1),
+                "2024-02-28T12:00:00Z",
+                "2024-02-29T00:00:00Z",
+            ),
+            (
+                Resolution::Days(1),
+                "2023-02-28T12:00:00Z",
+                "2023-03-01T00:00:00Z",
+            ),
+            (
+                Resolution::Weeks(1),
+                "2024-05-12T10:00:00Z",
+                "2024-05-13T00:00:00Z",
+            ),
+            (
+                Resolution::Weeks(1),
+                "2024-12-30T00:00:00Z",
+                "2025-01-06T00:00:00Z",
+            ),
+            (
+                Resolution::Months(1),
+                "2024-01-31T00:00:00Z",
+                "2024-02-01T00:00:00Z",
+            ),
+            (
+                Resolution::Months(1),
+                "2024-02-29T23:59:59Z",
+                "2024-03-01T00:00:00Z",
+            ),
+            (
+                Resolution::Months(1),
+                "2023-12-31T23:59:59Z",
+                "2024-01-01T00:00:00Z",
+            ),
+        ] {
+            let found = next_normalized_time_for_resolution(utc(time), resolution, Weekday::Mon);
+            assert_eq!(found, utc(next), "{} after {}", resolution, time);
+            // A boundary is its own candle's start
+            assert_eq!(
+                last_normalized_time_at_or_before(found, resolution, Weekday::Mon),
+                found,
+                "{} at {}",
+                resolution,
+                found
+            );
+        }
+    }
+
+    #[test]
+    fn candle_counts_across_month_ends_and_leap_days() {
+        for (resolution, from, to, candles) in [
+            (
+                Resolution::Days(1),
+                "2024-02-01T00:00:00Z",
+                "2024-02-29T23:59:59Z",
+                29,
+            ),
+            (
+                Resolution::Days(1),
+                "2023-02-01T00:00:00Z",
+                "2023-02-28T23:59:59Z",
+                28,
+            ),
+            (
+                Resolution::Minutes(60),
+                "2024-02-28T00:00:00Z",
+                "2024-03-01T00:00:00Z",
+                49,
+            ),
+            (
+                Resolution::Months(1),
+                "2023-11-15T00:00:00Z",
+                "2024-03-15T00:00:00Z",
+                4,
+            ),
+        ] {
+            assert_eq!(
+                expected_candle_times(utc(from), utc(to), resolution, Weekday::Mon).len(),
+                candles,
+                "{} from {} to {}",
+                resolution,
+                from,
+                to
+            );
+        }
+    }
+
+    #[test]
+    fn a_from_on_a_boundary_is_the_first_expected_candle() {
+        let from = utc("2024-05-12T14:00:00Z");
+        assert_eq!(
+            first_normalized_time_at_or_after(from, Resolution::Minutes(60), Weekday::Mon),
+            from
+        );
+        assert_eq!(
+            expected_candle_times(
+                from,
+                utc("2024-05-12T16:30:00Z"),
+                Resolution::Minutes(60),
+                Weekday::Mon
+            ),
+            vec![
+                from,
+                utc("2024-05-12T15:00:00Z"),
+                utc("2024-05-12T16:00:00Z")
+            ]
+        );
+    }
+
+    #[test]
+    fn expected_candle_times_across_resolutions() {
+        let from = utc("2023-12-31T22:00:00Z");
+        let to = utc("2024-01-01T02:00:00Z");
+        for (resolution, count, first, last) in [
+            ("1", 241, "2023-12-31T22:00:00Z", "2024-01-01T02:00:00Z"),
+            ("15", 17, "2023-12-31T22:00:00Z", "2024-01-01T02:00:00Z"),
+            ("60", 5, "2023-12-31T22:00:00Z", "2024-01-01T02:00:00Z"),
+            ("240", 1, "2024-01-01T00:00:00Z", "2024-01-01T00:00:00Z"),
+            ("D", 1, "2024-01-01T00:00:00Z", "2024-01-01T00:00:00Z"),
+        ] {
+            let resolution: Resolution = resolution.parse().unwrap();
+            let times = expected_candle_times(from, to, resolution, Weekday::Mon);
+            assert_eq!(times.len(), count, "{}", resolution);
+            assert_eq!(times.first(), Some(&utc(first)), "{}", resolution);
+            assert_eq!(times.last(), Some(&utc(last)), "{}", resolution);
+            assert_eq!(
+                candle_times(from, to, resolution, Weekday::Mon).count(),
+                count
+            );
+        }
+        // Calendar resolutions step by calendar weeks and months
+        let (from, to) = (utc("2024-01-01T00:00:00Z"), utc("2024-12-31T23:59:59Z"));
+        assert_eq!(
+            expected_candle_times(from, to, Resolution::Weeks(1), Weekday::Mon).len(),
+            53
+        );
+        assert_eq!(
+            expected_candle_times(from, to, Resolution::Months(1), Weekday::Mon).len(),
+            12
+        );
+        assert!(expected_candle_times(
+            utc("2024-01-01T00:00:01Z"),
+            utc("2024-01-01T00:00:59Z"),
+            Resolution::Minutes(1),
+            Weekday::Mon
+        )
+        .is_empty());
+    }
+}
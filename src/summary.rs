@@ -0,0 +1,416 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::daily::GapCalendar;
+use crate::display::{in_zone, DisplayInZone};
+use crate::error_category::ErrorCounts;
+use crate::latency::LatencyStats;
+use crate::periods::window_coverage;
+use crate::report::{GapReport, PeriodStatus};
+
+/// Roll-up of every `GapReport` produced during a run.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Summary {
+    /// Every period, including the ones counted in `no_data_periods` and `failed_periods`
+    pub periods: usize,
+    /// Periods the API answered `no_data` for
+    pub no_data_periods: usize,
+    /// Periods that couldn't be fetched. Their candles aren't part of the counts below.
+    pub failed_periods: usize,
+    pub expected: usize,
+    pub present: usize,
+    pub missing: usize,
+    pub longest_gap: Option<LongestGap>,
+    pub gap_histogram: GapHistogram,
+    pub per_symbol: BTreeMap<String, SymbolSummary>,
+    /// Only filled in when more than one resolution was checked
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub per_resolution: BTreeMap<String, SymbolSummary>,
+    /// Seed the randomized periods were generated from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencyStats>,
+    /// Failed request attempts by cause, retried ones included
+    #[serde(skip_serializing_if = "ErrorCounts::is_empty")]
+    pub errors: ErrorCounts,
+    /// Only filled in when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily: Option<GapCalendar>,
+}
+
+/// Counts for the reports of one symbol, or of one resolution.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SymbolSummary {
+    pub periods: usize,
+    pub no_data_periods: usize,
+    pub failed_periods: usize,
+    pub expected: usize,
+    pub present: usize,
+    pub missing: usize,
+    /// Length of the longest run of consecutive missing candles, in candles
+    pub longest_gap: usize,
+    /// Fraction of the checked window covered by this symbol's periods
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_coverage: Option<f64>,
+    /// The window checked for this symbol, which differs between symbols with their own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_from: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_to: Option<DateTime<Utc>>,
+    pub status: SymbolStatus,
+    /// Why the check of this symbol was abandoned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Whether every period of a symbol, or of a resolution, could be fetched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolStatus {
+    #[default]
+    Ok,
+    /// Some periods failed to fetch
+    Partial,
+    /// Every period failed to fetch, or the check was abandoned
+    Failed,
+}
+
+impl fmt::Display for SymbolStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            SymbolStatus::Ok => "ok",
+            SymbolStatus::Partial => "partial",
+            SymbolStatus::Failed => "failed",
+        })
+    }
+}
+
+/// The longest run of consecutive missing candles within a single period.
+#[derive(Debug, Clone, Serialize)]
+pub struct LongestGap {
+    pub symbol: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub candles: usize,
+}
+
+/// Number of gaps by length in candles. Serialized as a map from bucket label to count, in
+/// bucket order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GapHistogram {
+    counts: [usize; GapHistogram::BUCKETS.len()],
+}
+
+impl GapHistogram {
+    /// Label and largest gap length of each bucket. The last one takes every longer gap.
+    pub const BUCKETS: [(&'static str, usize); 5] = [
+        ("1", 1),
+        ("2-3", 3),
+        ("4-10", 10),
+        ("11-50", 50),
+        (">50", usize::MAX),
+    ];
+
+    pub fn add(&mut self, gap_candles: usize) {
+        let bucket = Self::BUCKETS
+            .iter()
+            .position(|(_, max)| gap_candles <= *max)
+            .unwrap();
+        self.counts[bucket] += 1;
+    }
+
+    /// `(label, count)` for every bucket, empty ones included.
+    pub fn buckets(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        Self::BUCKETS
+            .iter()
+            .zip(self.counts)
+            .map(|((label, _), count)| (*label, count))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.iter().all(|count| *count == 0)
+    }
+}
+
+impl Serialize for GapHistogram {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.buckets())
+    }
+}
+
+impl fmt::Display for GapHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let buckets: Vec<String> = self
+            .buckets()
+            .map(|(label, count)| format!("{}: {}", label, count))
+            .collect();
+        write!(f, "Gaps by length in candles: {}", buckets.join(", "))
+    }
+}
+
+impl Summary {
+    pub fn from_reports(reports: &[GapReport]) -> Self {
+        let mut summary = Summary::default();
+        for report in reports {
+            summary.add(report);
+        }
+        if summary.per_resolution.len() <= 1 {
+            summary.per_resolution.clear();
+        }
+        summary
+    }
+
+    pub fn add(&mut self, report: &GapReport) {
+        self.periods += 1;
+        self.per_symbol
+            .entry(report.symbol.clone())
+            .or_default()
+            .add(report);
+        self.per_resolution
+            .entry(report.resolution.to_string())
+            .or_default()
+            .add(report);
+        match report.status {
+            PeriodStatus::Ok => {}
+            PeriodStatus::NoData => self.no_data_periods += 1,
+            PeriodStatus::FetchError { .. } => {
+                self.failed_periods += 1;
+                return;
+            }
+        }
+
+        self.expected += report.expected;
+        self.present += report.present;
+        self.missing += report.missing.len();
+
+        let gaps = report.gap_ranges();
+        for gap in &gaps {
+            self.gap_histogram.add(gap.count);
+        }
+        let longest_run = gaps.into_iter().max_by_key(|gap| gap.count);
+        if let Some(run) = longest_run {
+            if self
+                .longest_gap
+                .as_ref()
+                .is_none_or(|gap| run.count > gap.candles)
+            {
+                self.longest_gap = Some(LongestGap {
+                    symbol: report.symbol.clone(),
+                    start: run.start,
+                    end: run.end,
+                    candles: run.count,
+                });
+            }
+        }
+    }
+
+    pub fn availability(&self) -> f64 {
+        availability(self.present, self.expected)
+    }
+
+    /// Marks `symbol` as failed because its check was abandoned with `error`. The reports it
+    /// produced before, if any, are expected to have been added already.
+    pub fn add_symbol_error(&mut self, symbol: &str, error: &str) {
+        let symbol = self.per_symbol.entry(symbol.to_string()).or_default();
+        symbol.status = SymbolStatus::Failed;
+        symbol.error = Some(error.to_string());
+    }
+
+    /// Records each symbol's `(lower, upper)` window, as returned by `window`, and how much of it
+    /// the symbol's reports covered.
+    pub fn add_window_coverage(
+        &mut self,
+        reports: &[GapReport],
+        window: impl Fn(&str) -> (DateTime<Utc>, DateTime<Utc>),
+    ) {
+        for (name, symbol) in &mut self.per_symbol {
+            let (lower, upper) = window(name);
+            let periods: Vec<(i64, i64)> = reports
+                .iter()
+                .filter(|report| report.symbol == *name)
+                .map(|report| (report.from.timestamp(), report.to.timestamp()))
+                .collect();
+            symbol.window_coverage = Some(window_coverage(&periods, lower, upper));
+            symbol.window_from = Some(lower);
+            symbol.window_to = Some(upper);
+        }
+    }
+}
+
+impl SymbolSummary {
+    fn add(&mut self, report: &GapReport) {
+        self.periods += 1;
+        match report.status {
+            PeriodStatus::Ok => {}
+            PeriodStatus::NoData => self.no_data_periods += 1,
+            PeriodStatus::FetchError { .. } => {
+                self.failed_periods += 1;
+                self.status = if self.failed_periods == self.periods {
+                    SymbolStatus::Failed
+                } else {
+                    SymbolStatus::Partial
+                };
+                return;
+            }
+        }
+        if self.status == SymbolStatus::Failed {
+            self.status = SymbolStatus::Partial;
+        }
+
+        self.expected += report.expected;
+        self.present += report.present;
+        self.missing += report.missing.len();
+        if let Some(run) = report.gap_ranges().into_iter().max_by_key(|gap| gap.count) {
+            self.longest_gap = self.longest_gap.max(run.count);
+        }
+    }
+}
+
+fn availability(present: usize, expected: usize) -> f64 {
+    if expected == 0 {
+        return 1.0;
+    }
+    present as f64 / expected as f64
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_in(f, Tz::UTC)
+    }
+}
+
+impl DisplayInZone for Summary {
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, tz: Tz) -> fmt::Result {
+        write!(
+            f,
+            "Checked {} period(s): {}/{} candles present ({:.2}%), {} missing",
+            self.periods,
+            self.present,
+            self.expected,
+            self.availability() * 100.0,
+            self.missing
+        )?;
+        if let Some(seed) = self.seed {
+            write!(f, "\nRandom periods seed: {}", seed)?;
+        }
+        if self.no_data_periods > 0 || self.failed_periods > 0 {
+            write!(
+                f,
+                "\n{} period(s) with no data, {} period(s) failed to fetch",
+                self.no_data_periods, self.failed_periods
+            )?;
+        }
+        if let Some(latency) = &self.latency {
+            write!(f, "\n{}", latency)?;
+        }
+        if !self.errors.is_empty() {
+            write!(f, "\n{}", self.errors)?;
+        }
+        if let Some(gap) = &self.longest_gap {
+            write!(
+                f,
+                "\nLongest gap: {} candle(s) for {} from {} to {}",
+                gap.candles,
+                gap.symbol,
+                in_zone(gap.start, tz),
+                in_zone(gap.end, tz)
+            )?;
+        }
+        if !self.gap_histogram.is_empty() {
+            write!(f, "\n{}", self.gap_histogram)?;
+        }
+
+        write_table(f, "symbol", &self.per_symbol)?;
+        for (name, symbol) in &self.per_symbol {
+            if let Some(error) = &symbol.error {
+                write!(f, "\nCheck of {} abandoned: {}", name, error)?;
+            }
+        }
+        // Only worth listing when symbols were checked over different windows
+        let mut windows = self
+            .per_symbol
+            .values()
+            .map(|symbol| (symbol.window_from, symbol.window_to));
+        if let Some(first) = windows.next() {
+            if windows.any(|window| window != first) {
+                for (name, symbol) in &self.per_symbol {
+                    if let (Some(from), Some(to)) = (symbol.window_from, symbol.window_to) {
+                        write!(
+                            f,
+                            "\nWindow of {}: {} to {}",
+                            name,
+                            in_zone(from, tz),
+                            in_zone(to, tz)
+                        )?;
+                    }
+                }
+            }
+        }
+        if !self.per_resolution.is_empty() {
+            writeln!(f)?;
+            write_table(f, "resolution", &self.per_resolution)?;
+        }
+        if let Some(daily) = &self.daily {
+            write!(f, "\n\n{}", daily)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_table(
+    f: &mut fmt::Formatter<'_>,
+    label: &str,
+    rows: &BTreeMap<String, SymbolSummary>,
+) -> fmt::Result {
+    write!(
+        f,
+        "\n{:<16} {:>8} {:>8} {:>7} {:>9} {:>9} {:>9} {:>13} {:>12} {:>9} {:>8}",
+        label,
+        "periods",
+        "no data",
+        "failed",
+        "expected",
+        "present",
+        "missing",
+        "availability",
+        "longest gap",
+        "coverage",
+        "status"
+    )?;
+    for (name, symbol) in rows {
+        write!(
+            f,
+            "\n{:<16} {:>8} {:>8} {:>7} {:>9} {:>9} {:>9} {:>13} {:>12} {:>9} {:>8}",
+            name,
+            symbol.periods,
+            symbol.no_data_periods,
+            symbol.failed_periods,
+            symbol.expected,
+            symbol.present,
+            symbol.missing,
+            // Nothing is known about a symbol whose every period failed
+            if symbol.failed_periods == symbol.periods {
+                "n/a".to_string()
+            } else {
+                format!(
+                    "{:.2}%",
+                    availability(symbol.present, symbol.expected) * 100.0
+                )
+            },
+            symbol.longest_gap,
+            symbol
+                .window_coverage
+                .map_or("-".to_string(), |coverage| format!(
+                    "{:.2}%",
+                    coverage * 100.0
+                )),
+            symbol.status
+        )?;
+    }
+    Ok(())
+}
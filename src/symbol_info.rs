@@ -0,0 +1,40 @@
+use serde::Deserialize;
+
+use crate::history::STATUS_ERROR;
+use crate::resolution::{unadvertised_resolutions, Resolution};
+
+/// The fields of a UDF `symbols` response that matter for gap checking. Datafeeds send many
+/// more, which are ignored.
+///
+/// Unknown symbols are answered with `{"s": "error", "errmsg": ...}` instead.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SymbolInfo {
+    #[serde(default)]
+    pub s: Option<String>,
+    #[serde(default)]
+    pub errmsg: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub ticker: Option<String>,
+    /// Resolutions in their wire form, e.g. `"1"`, `"60"` or `"1D"`
+    #[serde(default)]
+    pub supported_resolutions: Vec<String>,
+    /// Trading hours, e.g. `"24x7"` or `"0930-1600"`
+    #[serde(default)]
+    pub session: Option<String>,
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+impl SymbolInfo {
+    pub fn is_error(&self) -> bool {
+        self.s.as_deref() == Some(STATUS_ERROR)
+    }
+
+    /// Those of `requested` the symbol doesn't advertise. Empty when the datafeed doesn't list
+    /// its resolutions at all.
+    pub fn unsupported_resolutions(&self, requested: &[Resolution]) -> Vec<Resolution> {
+        unadvertised_resolutions(&self.supported_resolutions, requested)
+    }
+}
@@ -0,0 +1,113 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+
+/// A point in time given either absolutely (RFC3339 or a `2024-05-12` date) or relative to now
+/// (`now`, `90m`, `48h`, `14d`, `2w`). Relative expressions always point into the past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeExpression {
+    Now,
+    Ago(TimeDelta),
+    Absolute(DateTime<Utc>),
+    /// Midnight UTC of the day
+    Date(NaiveDate),
+}
+
+impl TimeExpression {
+    /// Fails when a relative expression reaches back further than dates can go.
+    pub fn resolve(&self, now: DateTime<Utc>) -> anyhow::Result<DateTime<Utc>> {
+        match *self {
+            TimeExpression::Now => Ok(now),
+            TimeExpression::Ago(delta) => now
+                .checked_sub_signed(delta)
+                .ok_or_else(|| anyhow::anyhow!("{} day(s) ago is out of range", delta.num_days())),
+            TimeExpression::Absolute(time) => Ok(time),
+            TimeExpression::Date(date) => Ok(date.and_time(Default::default()).and_utc()),
+        }
+    }
+
+    /// Whether this is a plain date, whose window bound gets snapped to a candle boundary.
+    pub fn is_date(&self) -> bool {
+        matches!(self, TimeExpression::Date(_))
+    }
+}
+
+impl FromStr for TimeExpression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("now") {
+            return Ok(TimeExpression::Now);
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(TimeExpression::Absolute(time.with_timezone(&Utc)));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(TimeExpression::Date(date));
+        }
+
+        let delta = parse_duration(s).map_err(|_| {
+            anyhow::anyhow!(
+                "Invalid time {:?}. Expected `now`, an RFC3339 timestamp, a date like \
+                 `2024-05-12` or a relative duration like `90m`, `48h`, `14d` or `2w`",
+                s
+            )
+        })?;
+        Ok(TimeExpression::Ago(delta))
+    }
+}
+
+/// Parses a duration such as `30s`, `90m`, `48h`, `14d` or `2w`.
+pub fn parse_duration(s: &str) -> anyhow::Result<TimeDelta> {
+    let s = s.trim();
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid duration {:?}. Expected something like `30s`, `90m`, `48h`, `14d` or `2w`",
+            s
+        )
+    };
+    let unit_start = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (amount, unit) = s.split_at(unit_start);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => None,
+    }
+    .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_relative_expressions_against_now() {
+        let now = DateTime::parse_from_rfc3339("2024-05-12T08:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let resolve = |s: &str| s.parse::<TimeExpression>().unwrap().resolve(now).unwrap();
+        assert_eq!(resolve("now"), now);
+        assert_eq!(resolve("90m"), now - TimeDelta::minutes(90));
+        assert_eq!(resolve("14d"), now - TimeDelta::days(14));
+        assert_eq!(
+            resolve("2024-05-01"),
+            NaiveDate::from_ymd_opt(2024, 5, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+        );
+    }
+
+    #[test]
+    fn out_of_range_expressions_are_errors() {
+        assert!("14x".parse::<TimeExpression>().is_err());
+        let far_back = "100000000d".parse::<TimeExpression>().unwrap();
+        assert!(far_back.resolve(Utc::now()).is_err());
+    }
+}
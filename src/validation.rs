@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::compare::Tolerance;
+use crate::history::{ApiResult, CandleData, StructuredApiResult, Time};
+
+/// A way in which a single candle violates basic OHLC invariants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    HighBelowLow,
+    HighBelowOpen,
+    HighBelowClose,
+    LowAboveOpen,
+    LowAboveClose,
+    ZeroVolume,
+}
+
+impl fmt::Display for AnomalyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            AnomalyKind::HighBelowLow => "high < low",
+            AnomalyKind::HighBelowOpen => "high < open",
+            AnomalyKind::HighBelowClose => "high < close",
+            AnomalyKind::LowAboveOpen => "low > open",
+            AnomalyKind::LowAboveClose => "low > close",
+            AnomalyKind::ZeroVolume => "volume == 0",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+impl CandleData {
+    /// Whether open, high, low and close are all the same price.
+    pub fn is_flat(&self) -> bool {
+        self.open == self.high && self.high == self.low && self.low == self.close
+    }
+
+    /// Whether any price is missing, e.g. `null` in the response for a candle that's still
+    /// forming. Such a candle is in the response but doesn't count as present.
+    pub fn is_partial(&self) -> bool {
+        [self.open, self.high, self.low, self.close]
+            .iter()
+            .any(|price| price.is_nan())
+    }
+
+    pub fn anomalies(&self) -> Vec<AnomalyKind> {
+        let checks = [
+            (self.high < self.low, AnomalyKind::HighBelowLow),
+            (self.high < self.open, AnomalyKind::HighBelowOpen),
+            (self.high < self.close, AnomalyKind::HighBelowClose),
+            (self.low > self.open, AnomalyKind::LowAboveOpen),
+            (self.low > self.close, AnomalyKind::LowAboveClose),
+            (self.volume == 0.0, AnomalyKind::ZeroVolume),
+        ];
+        checks
+            .into_iter()
+            .filter_map(|(failed, kind)| failed.then_some(kind))
+            .collect()
+    }
+}
+
+impl StructuredApiResult {
+    /// Every OHLC invariant violation in the result, ordered by candle time.
+    pub fn anomalies(&self) -> Vec<(Time, AnomalyKind)> {
+        self.sorted_candles()
+            .into_iter()
+            .flat_map(|(time, candle)| candle.anomalies().into_iter().map(move |kind| (time, kind)))
+            .collect()
+    }
+}
+
+/// Consecutive flat candles at the same price. Feeds sometimes fill missing data by repeating
+/// the previous close, which passes the presence check but isn't real trading.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FlatRun {
+    pub start: Time,
+    pub end: Time,
+    pub candles: usize,
+    pub price: f64,
+}
+
+impl StructuredApiResult {
+    /// Runs of at least `min_candles` consecutive flat candles at the same price, ordered by
+    /// time. A single flat candle can be legitimate in a quiet market, long runs rarely are.
+    pub fn flat_runs(&self, min_candles: usize) -> Vec<FlatRun> {
+        let mut runs: Vec<FlatRun> = vec![];
+        let mut current: Option<FlatRun> = None;
+        for (time, candle) in self.sorted_candles() {
+            current = match current {
+                Some(mut run) if candle.is_flat() && candle.close == run.price => {
+                    run.end = time;
+                    run.candles += 1;
+                    Some(run)
+                }
+                previous => {
+                    runs.extend(previous.filter(|run| run.candles >= min_candles));
+                    candle.is_flat().then_some(FlatRun {
+                        start: time,
+                        end: time,
+                        candles: 1,
+                        price: candle.close,
+                    })
+                }
+            };
+        }
+        runs.extend(current.filter(|run| run.candles >= min_candles));
+        runs
+    }
+}
+
+/// A timestamp that appeared more than once in a response. Only the last occurrence ends up in
+/// the `StructuredApiResult`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateCandle {
+    pub time: Time,
+    pub first: CandleData,
+    pub second: CandleData,
+}
+
+impl DuplicateCandle {
+    /// Whether the two occurrences carry different values rather than being a plain repeat.
+    /// Differences within the default [`Tolerance`] are treated as a repeat.
+    pub fn is_conflicting(&self) -> bool {
+        !self.first.approx_eq(&self.second, Tolerance::default())
+    }
+}
+
+impl StructuredApiResult {
+    /// Adds the candles of `other`, e.g. the next chunk of a period. Chunks can overlap, e.g.
+    /// when the server returns candles past the end of one, so a time present in both is fine
+    /// as long as the candles match within `tolerance`. Conflicting ones are returned and
+    /// `other`'s candle is kept, like the last occurrence is within a single response.
+    pub fn merge(
+        &mut self,
+        other: StructuredApiResult,
+        tolerance: Tolerance,
+    ) -> Vec<DuplicateCandle> {
+        let mut conflicts = vec![];
+        for (time, candle) in other.0 {
+            if let Some(previous) = self.0.insert(time, candle.clone()) {
+                if !previous.approx_eq(&candle, tolerance) {
+                    conflicts.push(DuplicateCandle {
+                        time,
+                        first: previous,
+                        second: candle,
+                    });
+                }
+            }
+        }
+        conflicts.sort_by_key(|conflict| conflict.time);
+        conflicts
+    }
+}
+
+impl ApiResult {
+    /// Every repeated timestamp, paired with the occurrence before it, in response order.
+    pub fn duplicate_candles(&self) -> Vec<DuplicateCandle> {
+        let mut seen: HashMap<Time, CandleData> = HashMap::with_capacity(self.time.len());
+        let mut duplicates = vec![];
+        for (time, candle) in self.candles() {
+            if let Some(previous) = seen.insert(time, candle.clone()) {
+                duplicates.push(DuplicateCandle {
+                    time,
+                    first: previous,
+                    second: candle,
+                });
+            }
+        }
+        duplicates
+    }
+
+    /// Adjacent `(time[i], time[i + 1])` pairs that break the strictly ascending order the UDF
+    /// protocol requires.
+    pub fn ordering_violations(&self) -> Vec<(Time, Time)> {
+        self.time
+            .windows(2)
+            .filter(|pair| pair[0] >= pair[1])
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
+
+    /// Timestamps outside `[from, to]`, which a request for that window shouldn't get back.
+    pub fn times_outside(&self, from: Time, to: Time) -> Vec<Time> {
+        self.time
+            .iter()
+            .copied()
+            .filter(|time| !(from..=to).contains(time))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result_with_times(time: Vec<Time>) -> ApiResult {
+        let n = time.len();
+        ApiResult {
+            s: "ok".to_string(),
+            time,
+            close: vec![1.5; n],
+            open: vec![1.0; n],
+            high: vec![2.0; n],
+            low: vec![0.5; n],
+            volume: Some(vec![10.0; n]),
+            next_time: None,
+            errmsg: None,
+        }
+    }
+
+    #[test]
+    fn shuffled_times_are_ordering_violations() {
+        let result = result_with_times(vec![60, 180, 120, 240, 240, 300]);
+        assert_eq!(result.ordering_violations(), vec![(180, 120), (240, 240)]);
+        assert!(result_with_times(vec![60, 120, 180])
+            .ordering_violations()
+            .is_empty());
+    }
+
+    fn candles(close: f64) -> StructuredApiResult {
+        let candle = CandleData {
+            close,
+            open: 1.0,
+            high: 2.0,
+            low: 0.5,
+            volume: 10.0,
+        };
+        StructuredApiResult(HashMap::from([(60, candle.clone()), (120, candle)]))
+    }
+
+    #[test]
+    fn merging_identical_overlapping_chunks_has_no_conflicts() {
+        let mut merged = candles(1.5);
+        let mut next = candles(1.5);
+        next.0.insert(180, next.0[&120].clone());
+        assert!(merged.merge(next, Tolerance::default()).is_empty());
+        assert_eq!(merged.0.len(), 3);
+
+        // A null price repeated by both chunks is the same candle
+        let mut merged = candles(f64::NAN);
+        assert!(merged
+            .merge(candles(f64::NAN), Tolerance::default())
+            .is_empty());
+    }
+
+    #[test]
+    fn merging_conflicting_overlapping_chunks_reports_them() {
+        let mut merged = candles(1.5);
+        let conflicts = merged.merge(candles(1.6), Tolerance::default());
+        assert_eq!(
+            conflicts
+                .iter()
+                .map(|conflict| conflict.time)
+                .collect::<Vec<_>>(),
+            vec![60, 120]
+        );
+        assert!(conflicts.iter().all(DuplicateCandle::is_conflicting));
+        // The later chunk's candle is kept
+        assert_eq!(merged.0[&60].close, 1.6);
+        assert_eq!(merged.0.len(), 2);
+
+        let mut merged = candles(1.5);
+        assert_eq!(
+            merged.merge(candles(f64::NAN), Tolerance::default()).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn times_outside_the_window_are_reported() {
+        let result = result_with_times(vec![0, 60, 120, 180, 240]);
+        assert_eq!(result.times_outside(60, 180), vec![0, 240]);
+        assert!(result.times_outside(0, 240).is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::display::{timestamp_in_zone, DisplayInZone};
+use crate::history::{StructuredApiResult, Time};
+
+/// Volume totals and per-candle statistics for the candles of one period.
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeStats {
+    pub candles: usize,
+    pub total: f64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Candles that reported no volume at all
+    pub zero_volume: Vec<Time>,
+    /// Candles whose volume is more than the z-score threshold standard deviations above the
+    /// mean
+    pub outliers: Vec<VolumeOutlier>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VolumeOutlier {
+    pub time: Time,
+    pub volume: f64,
+    pub z_score: f64,
+}
+
+impl StructuredApiResult {
+    /// `None` for a result without candles.
+    pub fn volume_stats(&self, z_threshold: f64) -> Option<VolumeStats> {
+        let candles: Vec<(Time, f64)> = self
+            .sorted_candles()
+            .into_iter()
+            .map(|(time, candle)| (time, candle.volume))
+            .collect();
+        if candles.is_empty() {
+            return None;
+        }
+
+        let count = candles.len() as f64;
+        let total: f64 = candles.iter().map(|(_, volume)| volume).sum();
+        let mean = total / count;
+        let variance = candles
+            .iter()
+            .map(|(_, volume)| (volume - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        let std_dev = variance.sqrt();
+
+        let outliers = if std_dev > 0.0 {
+            candles
+                .iter()
+                .map(|(time, volume)| VolumeOutlier {
+                    time: *time,
+                    volume: *volume,
+                    z_score: (volume - mean) / std_dev,
+                })
+                .filter(|outlier| outlier.z_score > z_threshold)
+                .collect()
+        } else {
+            vec![]
+        };
+
+        Some(VolumeStats {
+            candles: candles.len(),
+            total,
+            min: candles
+                .iter()
+                .map(|(_, volume)| *volume)
+                .fold(f64::INFINITY, f64::min),
+            max: candles
+                .iter()
+                .map(|(_, volume)| *volume)
+                .fold(f64::NEG_INFINITY, f64::max),
+            mean,
+            zero_volume: candles
+                .iter()
+                .filter(|(_, volume)| *volume == 0.0)
+                .map(|(time, _)| *time)
+                .collect(),
+            outliers,
+        })
+    }
+}
+
+impl fmt::Display for VolumeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "volume over {} candle(s): total {}, min {}, max {}, mean {:.2}",
+            self.candles, self.total, self.min, self.max, self.mean
+        )?;
+        if !self.zero_volume.is_empty() {
+            write!(f, ", {} with zero volume", self.zero_volume.len())?;
+        }
+        if !self.outliers.is_empty() {
+            write!(f, ", {} outlier(s)", self.outliers.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for VolumeOutlier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_in(f, Tz::UTC)
+    }
+}
+
+impl DisplayInZone for VolumeOutlier {
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, tz: Tz) -> fmt::Result {
+        write!(
+            f,
+            "{}: volume {} (z-score {:.1})",
+            timestamp_in_zone(self.time, tz),
+            self.volume,
+            self.z_score
+        )
+    }
+}
@@ -0,0 +1,224 @@
+mod common;
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use common::{client, datafeed, history, HOUR, T0};
+use serde_json::json;
+use tv_debug::{GapReport, PeriodStatus, Resolution, RetryPolicy};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn at(timestamp: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(timestamp, 0).unwrap()
+}
+
+#[tokio::test]
+async fn reports_the_candles_missing_from_the_response() {
+    let server = datafeed(history(&[T0, T0 + HOUR, T0 + 3 * HOUR, T0 + 5 * HOUR])).await;
+    let report = client(&server)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + 5 * HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.status, PeriodStatus::Ok);
+    assert_eq!(report.expected, 6);
+    assert_eq!(report.present, 4);
+    assert_eq!(report.missing, vec![at(T0 + 2 * HOUR), at(T0 + 4 * HOUR)]);
+}
+
+#[tokio::test]
+async fn no_data_leaves_every_candle_missing() {
+    let server = datafeed(json!({"s": "no_data"})).await;
+    let report = client(&server)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + 2 * HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.status, PeriodStatus::NoData);
+    assert_eq!(report.expected, 3);
+    assert_eq!(report.present, 0);
+    assert_eq!(report.missing.len(), 3);
+}
+
+#[tokio::test]
+async fn expects_candles_at_resolution_boundaries_only() {
+    // 90 minute candles are aligned to the epoch, not to the hour
+    let first = (T0 / 5400 + 1) * 5400;
+    let server = datafeed(history(&[first, first + 5400])).await;
+    let report = client(&server)
+        .check_period("SOL/USDC", Resolution::Minutes(90), T0 + 60, T0 + 3 * HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.expected, 2);
+    assert_eq!(report.present, 2);
+    assert!(report.missing.is_empty());
+
+    // Daily candles start at midnight, whatever the time of day of `from`
+    let midnight = (T0 / 86400 + 1) * 86400;
+    let server = datafeed(history(&[midnight])).await;
+    let report = client(&server)
+        .check_period("SOL/USDC", Resolution::Days(1), T0, midnight + 2 * 86400)
+        .await
+        .unwrap();
+    assert_eq!(report.expected, 3);
+    assert_eq!(report.present, 1);
+    assert_eq!(
+        report.missing,
+        vec![at(midnight + 86400), at(midnight + 2 * 86400)]
+    );
+}
+
+#[tokio::test]
+async fn candles_at_both_ends_of_the_window_are_expected() {
+    // Both ends fall on a candle boundary, and both candles are missing
+    let server = datafeed(history(&[T0 + HOUR, T0 + 2 * HOUR])).await;
+    let report = client(&server)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + 3 * HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.expected, 4);
+    assert_eq!(report.missing, vec![at(T0), at(T0 + 3 * HOUR)]);
+
+    // A second past the end leaves the last boundary inside, a second after the start doesn't
+    let report = client(&server)
+        .check_period(
+            "SOL/USDC",
+            Resolution::Minutes(60),
+            T0 + 1,
+            T0 + 3 * HOUR + 1,
+        )
+        .await
+        .unwrap();
+    assert_eq!(report.expected, 3);
+    assert_eq!(report.missing, vec![at(T0 + 3 * HOUR)]);
+}
+
+#[tokio::test]
+async fn candles_repeated_by_overlapping_chunks_count_once() {
+    // Returns the whole period for every chunk, so the chunks overlap
+    let times: Vec<i64> = (0..6).map(|i| T0 + i * HOUR).collect();
+    let server = datafeed(history(&times)).await;
+    let report = client(&server)
+        .with_max_bars(2)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + 5 * HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.expected, 6);
+    assert_eq!(report.present, 6);
+    assert_eq!(report.returned, 6);
+    assert!(report.duplicates.is_empty());
+}
+
+#[tokio::test]
+async fn candles_with_null_prices_are_partial() {
+    let mut body = history(&[T0, T0 + HOUR]);
+    body["close"][1] = json!(null);
+    let server = datafeed(body).await;
+    let report = client(&server)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.expected, 2);
+    assert_eq!(report.present, 1);
+    assert_eq!(report.partial, vec![at(T0 + HOUR)]);
+    assert!(report.missing.is_empty());
+}
+
+#[tokio::test]
+async fn candles_outside_the_window_are_reported() {
+    let server = datafeed(history(&[T0 - HOUR, T0, T0 + HOUR, T0 + 2 * HOUR])).await;
+    let report = client(&server)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.outside_window, vec![T0 - HOUR, T0 + 2 * HOUR]);
+    assert_eq!(report.present, 2);
+    assert!(report.has_problems());
+}
+
+#[tokio::test]
+async fn a_response_without_volume_is_still_checked() {
+    let mut body = history(&[T0, T0 + 2 * HOUR]);
+    body.as_object_mut().unwrap().remove("volume");
+    let server = datafeed(body).await;
+    let report = client(&server)
+        .with_ohlc_validation(true)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + 2 * HOUR)
+        .await
+        .unwrap();
+    assert!(report.volume_unavailable);
+    assert_eq!(report.present, 2);
+    assert_eq!(report.missing, vec![at(T0 + HOUR)]);
+    // The made up zero volumes aren't anomalies
+    assert!(report.anomalies.is_empty());
+}
+
+#[tokio::test]
+async fn daily_candles_at_a_non_midnight_offset() {
+    // T0 is 22:00 UTC, the daily boundary of a provider two hours behind midnight
+    let times = [T0, T0 + 24 * HOUR, T0 + 48 * HOUR];
+    let server = datafeed(history(&times)).await;
+    let aligned = |offset| client(&server).with_alignment_offset(TimeDelta::minutes(offset));
+    let (from, to) = (T0 - HOUR, T0 + 49 * HOUR);
+
+    let report = aligned(-120)
+        .check_period("SOL/USDC", Resolution::Days(1), from, to)
+        .await
+        .unwrap();
+    assert_eq!(report.expected, 3);
+    assert_eq!(report.present, 3);
+    assert!(report.missing.is_empty());
+    assert_eq!(report.status, PeriodStatus::Ok);
+
+    // Assuming midnight, every expected candle misses
+    let report = aligned(0)
+        .check_period("SOL/USDC", Resolution::Days(1), from, to)
+        .await
+        .unwrap();
+    assert_eq!(report.present, 0);
+    assert_eq!(report.missing, vec![at(T0 + 2 * HOUR), at(T0 + 26 * HOUR)]);
+}
+
+#[tokio::test]
+async fn a_response_slower_than_the_timeout_fails_the_period() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/history"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(history(&[T0]))
+                .set_delay(Duration::from_secs(5)),
+        )
+        .mount(&server)
+        .await;
+    let http_client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(200))
+        .build()
+        .unwrap();
+    let client = client(&server)
+        .with_http_client(http_client)
+        .with_retry_policy(RetryPolicy {
+            max_retries: 0,
+            base_delay: Duration::ZERO,
+        });
+    let checked = client.check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + HOUR);
+
+    let error = tokio::time::timeout(Duration::from_secs(2), checked)
+        .await
+        .expect("the check should give up at the client timeout")
+        .unwrap_err();
+    assert!(error.to_string().contains("timed out"), "{:#}", error);
+    let report = GapReport::failed(
+        "SOL/USDC",
+        Resolution::Minutes(60),
+        at(T0),
+        at(T0 + HOUR),
+        &error,
+    );
+    assert!(matches!(
+        report.status,
+        PeriodStatus::FetchError {
+            http_status: None,
+            ..
+        }
+    ));
+}
@@ -0,0 +1,41 @@
+//! Helpers for the tests that check periods against a mock datafeed.
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use tv_debug::ApiClient;
+use url::Url;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// 2023-11-14 22:00:00 UTC, a boundary of every intraday resolution up to 2 hours.
+pub const T0: i64 = 1_699_999_200;
+pub const HOUR: i64 = 3600;
+
+/// An `ok` history response with a candle at each of `times`.
+pub fn history(times: &[i64]) -> Value {
+    let n = times.len();
+    json!({
+        "s": "ok",
+        "time": times,
+        "open": vec![1.0; n],
+        "high": vec![2.0; n],
+        "low": vec![0.5; n],
+        "close": vec![1.5; n],
+        "volume": vec![10.0; n],
+    })
+}
+
+/// A datafeed answering every `history` request with `body`.
+pub async fn datafeed(body: Value) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/history"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .mount(&server)
+        .await;
+    server
+}
+
+pub fn client(server: &MockServer) -> ApiClient {
+    ApiClient::new(Url::parse(&server.uri()).unwrap())
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use std::io::Write;
+
+use common::{client, history, HOUR, T0};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tv_debug::Resolution;
+use wiremock::matchers::{header_regex, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn gzip_encoded_history_is_decoded() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(history(&[T0, T0 + HOUR]).to_string().as_bytes())
+        .unwrap();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/history"))
+        .and(header_regex("accept-encoding", "gzip"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("content-encoding", "gzip")
+                .insert_header("content-type", "application/json")
+                .set_body_bytes(encoder.finish().unwrap()),
+        )
+        .mount(&server)
+        .await;
+
+    let report = client(&server)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.expected, 2);
+    assert_eq!(report.present, 2);
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::{client, history, HOUR, T0};
+use reqwest::header::{HeaderMap, HeaderValue};
+use tv_debug::Resolution;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn the_api_key_and_custom_headers_are_sent() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/history"))
+        .and(header("authorization", "Bearer abc123"))
+        .and(header("x-tenant", "desk-7"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(history(&[T0, T0 + HOUR])))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-tenant", HeaderValue::from_static("desk-7"));
+    headers.insert("authorization", HeaderValue::from_static("Bearer abc123"));
+    let report = client(&server)
+        .with_headers(headers)
+        .check_period("SOL/USDC", Resolution::Minutes(60), T0, T0 + HOUR)
+        .await
+        .unwrap();
+    assert_eq!(report.present, 2);
+}
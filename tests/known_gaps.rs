@@ -0,0 +1,60 @@
+//! Checks that the datafeed at `BASE_URL` still has gaps seen on its chart, and that they're
+//! still detected. Needs the network, so it only runs with
+//! `cargo test --test known_gaps -- --ignored`.
+
+use chrono::{DateTime, Utc};
+use tv_debug::{ApiClient, Resolution};
+use url::Url;
+
+const SYMBOL: &str = "SOL/USDC";
+
+/// A time and its unix timestamp, computed by hand.
+type KnownTime = (&'static str, i64);
+
+/// Bounds of periods with no hourly candles between them. The candles at both bounds exist.
+const KNOWN_GAPS: [(KnownTime, KnownTime); 3] = [
+    (
+        ("2024-05-12T21:00:00Z", 1715547600),
+        ("2024-05-13T02:00:00Z", 1715565600),
+    ),
+    (
+        ("2024-05-13T05:00:00Z", 1715576400),
+        ("2024-05-13T08:00:00Z", 1715587200),
+    ),
+    (
+        ("2024-05-13T10:00:00Z", 1715594400),
+        ("2024-05-13T12:00:00Z", 1715601600),
+    ),
+];
+
+#[test]
+fn known_gap_timestamps_match_their_times() {
+    for (from, to) in KNOWN_GAPS {
+        for (time, timestamp) in [from, to] {
+            let parsed = DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc);
+            assert_eq!(parsed.timestamp(), timestamp, "{}", time);
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs BASE_URL and the network"]
+async fn known_gaps_are_still_missing() {
+    dotenv::dotenv().ok();
+    let base_url = std::env::var("BASE_URL").expect("BASE_URL should point at the datafeed");
+    let client = ApiClient::new(Url::parse(&base_url).unwrap());
+    for ((from_time, from), (to_time, to)) in KNOWN_GAPS {
+        let report = client
+            .check_period(SYMBOL, Resolution::Minutes(60), from + 1, to - 1)
+            .await
+            .unwrap();
+        assert!(report.expected > 0);
+        assert_eq!(
+            report.present, 0,
+            "Expected no candles between {} and {}",
+            from_time, to_time
+        );
+    }
+}